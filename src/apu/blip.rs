@@ -0,0 +1,214 @@
+use std::f64::consts::PI;
+
+// sub-sample positions a step can start at
+const PHASES: usize = 32;
+
+// output samples each step is spread over
+const KERNEL_WIDTH: usize = 16;
+
+// fraction of the output Nyquist frequency that's let through
+const CUTOFF: f64 = 0.9;
+
+// fixed point position in output samples, 32 fractional bits
+const FRAC_BITS: u32 = 32;
+
+// band-limited step synthesis. Changes in amplitude are recorded as deltas
+// at the clock they happen on, smeared over a few output samples by a
+// windowed sinc and integrated back into a waveform when read, so square
+// edges don't alias the way plain sampling does
+pub struct BlipBuffer {
+    // output samples per clock
+    step: u64,
+    // current clock's position relative to `deltas[0]`
+    time: u64,
+    deltas: Vec<f32>,
+    integrator: f32,
+    kernel: Box<[[f32; KERNEL_WIDTH]; PHASES]>,
+}
+
+impl BlipBuffer {
+    pub fn new(clock_rate: u32, sample_rate: u32) -> Self {
+        Self {
+            step: ((sample_rate as u64) << FRAC_BITS) / clock_rate as u64,
+            time: 0,
+            deltas: Vec::new(),
+            integrator: 0.0,
+            kernel: Box::new(build_kernel()),
+        }
+    }
+
+    // `delta` is the change in amplitude at the current clock
+    pub fn add_delta(&mut self, delta: f32) {
+        let whole = (self.time >> FRAC_BITS) as usize;
+        let fraction = self.time & ((1 << FRAC_BITS) - 1);
+        let phase = ((fraction * PHASES as u64) >> FRAC_BITS) as usize;
+
+        if self.deltas.len() < whole + KERNEL_WIDTH {
+            self.deltas.resize(whole + KERNEL_WIDTH, 0.0);
+        }
+
+        for (slot, weight) in self.deltas[whole..].iter_mut().zip(&self.kernel[phase]) {
+            *slot += delta * weight;
+        }
+    }
+
+    pub fn advance(&mut self, clocks: u32) {
+        self.time += clocks as u64 * self.step;
+    }
+
+    // output samples that no future delta can touch any more
+    pub fn samples_available(&self) -> usize {
+        (self.time >> FRAC_BITS) as usize
+    }
+
+    // removes up to `count` finished samples and appends them to `out`
+    pub fn read_samples(&mut self, count: usize, out: &mut Vec<f32>) {
+        let count = count.min(self.samples_available());
+        if self.deltas.len() < count {
+            self.deltas.resize(count, 0.0);
+        }
+
+        for delta in self.deltas.drain(..count) {
+            self.integrator += delta;
+            out.push(self.integrator);
+        }
+
+        self.time -= (count as u64) << FRAC_BITS;
+    }
+}
+
+// one row per phase, each a windowed sinc impulse centred KERNEL_WIDTH / 2
+// samples after the step and scaled so the row sums to 1
+fn build_kernel() -> [[f32; KERNEL_WIDTH]; PHASES] {
+    let mut kernel = [[0.0; KERNEL_WIDTH]; PHASES];
+    let half = KERNEL_WIDTH as f64 / 2.0;
+
+    for (phase, row) in kernel.iter_mut().enumerate() {
+        let offset = phase as f64 / PHASES as f64;
+
+        let taps: Vec<f64> = (0..KERNEL_WIDTH)
+            .map(|i| {
+                let t = i as f64 - half - offset;
+                let x = t * CUTOFF;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (PI * x).sin() / (PI * x)
+                };
+
+                // Blackman window over [-half, half]
+                let w = ((t + half) / (2.0 * half)).clamp(0.0, 1.0);
+                let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+
+                sinc * window
+            })
+            .collect();
+
+        let sum: f64 = taps.iter().sum();
+        for (slot, tap) in row.iter_mut().zip(taps) {
+            *slot = (tap / sum) as f32;
+        }
+    }
+
+    kernel
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLOCK_RATE: u32 = 4_194_304;
+
+    fn read_all(buffer: &mut BlipBuffer) -> Vec<f32> {
+        let mut samples = Vec::new();
+        buffer.read_samples(buffer.samples_available(), &mut samples);
+        samples
+    }
+
+    #[test]
+    fn a_second_of_clocks_makes_a_second_of_samples() {
+        for sample_rate in [22_050, 44_100, 48_000, 96_000] {
+            let mut buffer = BlipBuffer::new(CLOCK_RATE, sample_rate);
+            for _ in 0..CLOCK_RATE / 4 {
+                buffer.advance(4);
+            }
+            assert_eq!(buffer.samples_available(), sample_rate as usize);
+
+            // reading some keeps the rest, and the clock's position
+            let mut samples = Vec::new();
+            buffer.read_samples(1000, &mut samples);
+            assert_eq!(samples.len(), 1000);
+            buffer.advance(CLOCK_RATE);
+            assert_eq!(buffer.samples_available(), 2 * sample_rate as usize - 1000);
+        }
+    }
+
+    #[test]
+    fn a_step_settles_within_the_kernel() {
+        let mut buffer = BlipBuffer::new(CLOCK_RATE, 48_000);
+        buffer.add_delta(1.0);
+        buffer.advance(CLOCK_RATE / 1000);
+        let samples = read_all(&mut buffer);
+        assert!(samples.len() > 2 * KERNEL_WIDTH);
+
+        // ringing of a few percent either side of the edge in the middle
+        let half = KERNEL_WIDTH / 2;
+        assert!(samples[..half].iter().all(|s| s.abs() < 0.06));
+        assert!(
+            samples[half..KERNEL_WIDTH]
+                .iter()
+                .all(|s| (s - 1.0).abs() < 0.06)
+        );
+        assert!(
+            samples[KERNEL_WIDTH..]
+                .iter()
+                .all(|s| (s - 1.0).abs() < 1e-5)
+        );
+
+        // deltas add up
+        buffer.add_delta(-1.5);
+        buffer.advance(CLOCK_RATE / 1000);
+        let samples = read_all(&mut buffer);
+        assert!((samples.last().unwrap() + 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn a_step_between_samples_lands_between_them() {
+        let mut early = BlipBuffer::new(CLOCK_RATE, 48_000);
+        let mut late = BlipBuffer::new(CLOCK_RATE, 48_000);
+        early.add_delta(1.0);
+        // half an output sample
+        late.advance(CLOCK_RATE / 48_000 / 2);
+        late.add_delta(1.0);
+
+        early.advance(CLOCK_RATE / 1000);
+        late.advance(CLOCK_RATE / 1000);
+        let early = read_all(&mut early);
+        let late = read_all(&mut late);
+
+        let half = KERNEL_WIDTH / 2;
+        assert!(late[half] < early[half] - 0.1);
+        assert!(late[half + 1] > 0.5);
+        assert!((late.last().unwrap() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn reading_as_it_goes_matches_reading_at_the_end() {
+        let mut at_end = BlipBuffer::new(CLOCK_RATE, 44_100);
+        let mut as_it_goes = BlipBuffer::new(CLOCK_RATE, 44_100);
+        let mut samples = Vec::new();
+
+        for i in 0..2000 {
+            if i % 37 == 0 {
+                let delta = if i % 2 == 0 { 0.5 } else { -0.25 };
+                at_end.add_delta(delta);
+                as_it_goes.add_delta(delta);
+            }
+            at_end.advance(23);
+            as_it_goes.advance(23);
+            samples.extend(read_all(&mut as_it_goes));
+        }
+
+        assert_eq!(samples, read_all(&mut at_end));
+    }
+}
@@ -0,0 +1,1288 @@
+mod blip;
+pub mod wav;
+
+use std::io;
+
+use crate::bus::{Clock, MemoryMappedDevice};
+use crate::model::Model;
+use blip::BlipBuffer;
+
+// T-cycles per second
+const CLOCK_RATE: u32 = 4_194_304;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+// the output capacitor's charge factor per T-cycle, which makes it a
+// high-pass filter that takes out the DACs' DC offset
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum HighPass {
+    #[default]
+    Dmg,
+    Cgb,
+    Off,
+}
+
+impl HighPass {
+    // the factor per output sample, None when filtering is off
+    fn charge_factor(self, sample_rate: u32) -> Option<f32> {
+        let per_cycle: f64 = match self {
+            HighPass::Dmg => 0.999958,
+            HighPass::Cgb => 0.998943,
+            HighPass::Off => return None,
+        };
+
+        Some(per_cycle.powf(CLOCK_RATE as f64 / sample_rate as f64) as f32)
+    }
+}
+
+// `capacitor` carries the filter's state from one sample to the next
+fn high_pass(input: f32, capacitor: &mut f32, charge_factor: Option<f32>) -> f32 {
+    let Some(charge_factor) = charge_factor else {
+        return input;
+    };
+
+    let output = input - *capacitor;
+    *capacitor = input - output * charge_factor;
+    output
+}
+
+// somewhere for the APU's output to go, like an audio device or a file
+pub trait AudioSink {
+    // interleaved left/right samples at the APU's sample rate
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()>;
+
+    // the same span of time for each channel on its own, before NR50/NR51.
+    // Only called while the APU is capturing channels
+    fn write_channel_samples(&mut self, _channels: &[Vec<f32>; 4]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// each channel's output run through its own blip buffer, for sinks
+// that want the channels separately
+struct ChannelCapture {
+    buffers: [BlipBuffer; 4],
+    output: [f32; 4],
+    capacitors: [f32; 4],
+}
+
+impl ChannelCapture {
+    fn new(sample_rate: u32) -> Self {
+        Self {
+            buffers: std::array::from_fn(|_| BlipBuffer::new(CLOCK_RATE, sample_rate)),
+            output: [0.0; 4],
+            capacitors: [0.0; 4],
+        }
+    }
+}
+
+// the frame sequencer steps on the falling edge of this bit of the
+// internal divider, 512 times a second
+const FRAME_SEQUENCER_BIT: u16 = 1 << 12;
+
+// waveforms selected by NR11/NR21 bits 6-7
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75%
+];
+
+// bits that read back as 1 for 0xFF10-0xFF2F, write-only and unused bits
+const READ_MASKS: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // unused, NR21-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // unused, NR41-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // unused
+];
+
+// shared by all four channels, 64 steps or 256 for the wave channel
+#[derive(Debug, Default)]
+struct Length {
+    max: u16,
+    counter: u16,
+    enabled: bool,
+}
+
+impl Length {
+    fn new(max: u16) -> Self {
+        Self {
+            max,
+            ..Default::default()
+        }
+    }
+
+    fn load(&mut self, value: u8) {
+        self.counter = self.max - value as u16;
+    }
+
+    // returns true when the counter just ran out
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            self.counter == 0
+        } else {
+            false
+        }
+    }
+
+    // NRx4 bit 6. Enabling it while the frame sequencer's next step won't
+    // clock length clocks it once right away, returns true if that ran out
+    fn write_enable(&mut self, enabled: bool, extra_clock: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enabled;
+
+        !was_enabled && extra_clock && self.clock()
+    }
+
+    fn trigger(&mut self, extra_clock: bool) {
+        if self.counter == 0 {
+            self.counter = self.max;
+            if self.enabled && extra_clock {
+                self.counter -= 1;
+            }
+        }
+    }
+}
+
+// volume envelope of the square and noise channels, set by NRx2
+#[derive(Debug, Default)]
+struct Envelope {
+    initial: u8,
+    increase: bool,
+    period: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn write(&mut self, value: u8) {
+        self.initial = value >> 4;
+        self.increase = value & 0x08 != 0;
+        self.period = value & 0x07;
+    }
+
+    // the upper 5 bits of NRx2 double as the DAC power switch
+    fn dac_enabled(&self) -> bool {
+        self.initial != 0 || self.increase
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.initial;
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+
+    fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+// frequency sweep, channel 1 only
+#[derive(Debug, Default)]
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    shadow: u16,
+    enabled: bool,
+    // a calculation in negate mode was made since the last trigger
+    negated: bool,
+}
+
+impl Sweep {
+    // the next frequency, or None if it overflows and disables the channel
+    fn calculate(&mut self) -> Option<u16> {
+        let delta = self.shadow >> self.shift;
+        let frequency = if self.negate {
+            self.negated = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        };
+
+        (frequency <= 2047).then_some(frequency)
+    }
+
+    fn reload_timer(&mut self) {
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+}
+
+// channels 1 and 2
+#[derive(Debug, Default)]
+struct Square {
+    enabled: bool,
+    sweep: Option<Sweep>,
+    length: Length,
+    envelope: Envelope,
+    duty: u8,
+    duty_step: u8,
+    frequency: u16,
+    timer: u32,
+}
+
+impl Square {
+    fn new(sweep: bool) -> Self {
+        Self {
+            sweep: sweep.then(Sweep::default),
+            length: Length::new(64),
+            ..Default::default()
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    // runs one T-cycle, returns true if the output may have changed
+    fn tick(&mut self) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        self.timer -= 1;
+        if self.timer > 0 {
+            return false;
+        }
+
+        self.timer = self.period();
+        self.duty_step = (self.duty_step + 1) % 8;
+        true
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled {
+            DUTY_PATTERNS[self.duty as usize][self.duty_step as usize] * self.envelope.volume
+        } else {
+            0
+        }
+    }
+
+    fn write(&mut self, register: u16, value: u8, extra_clock: bool) {
+        match register {
+            0 => {
+                if let Some(sweep) = self.sweep.as_mut() {
+                    sweep.period = (value >> 4) & 0x07;
+                    sweep.negate = value & 0x08 != 0;
+                    sweep.shift = value & 0x07;
+
+                    // leaving negate mode after it was used kills the channel
+                    if !sweep.negate && sweep.negated {
+                        self.enabled = false;
+                    }
+                }
+            }
+            1 => {
+                self.duty = value >> 6;
+                self.length.load(value & 0x3F);
+            }
+            2 => {
+                self.envelope.write(value);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.frequency = (self.frequency & 0x700) | value as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | ((value as u16 & 0x07) << 8);
+                if self.length.write_enable(value & 0x40 != 0, extra_clock) {
+                    self.enabled = false;
+                }
+                if value & 0x80 != 0 {
+                    self.trigger(extra_clock);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn trigger(&mut self, extra_clock: bool) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger(extra_clock);
+        self.envelope.trigger();
+        self.timer = self.period();
+
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.shadow = self.frequency;
+            sweep.reload_timer();
+            sweep.enabled = sweep.period != 0 || sweep.shift != 0;
+            sweep.negated = false;
+
+            // the overflow check runs straight away
+            if sweep.shift != 0 && sweep.calculate().is_none() {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn clock_sweep(&mut self) {
+        let Some(sweep) = self.sweep.as_mut() else {
+            return;
+        };
+
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer > 0 {
+            return;
+        }
+        sweep.reload_timer();
+
+        if !sweep.enabled || sweep.period == 0 {
+            return;
+        }
+
+        match sweep.calculate() {
+            Some(frequency) if sweep.shift != 0 => {
+                sweep.shadow = frequency;
+                self.frequency = frequency;
+
+                // the new frequency is checked again, but not used
+                if sweep.calculate().is_none() {
+                    self.enabled = false;
+                }
+            }
+            Some(_) => {}
+            None => self.enabled = false,
+        }
+    }
+}
+
+// channel 3, plays back 32 4-bit samples from wave RAM
+#[derive(Debug, Default)]
+struct Wave {
+    enabled: bool,
+    dac_enabled: bool,
+    length: Length,
+    volume: u8, // NR32 bits 5-6
+    frequency: u16,
+    timer: u32,
+    position: u8,
+    // the last byte fetched from wave RAM, played until the next fetch
+    sample_byte: u8,
+    ram: [u8; 16],
+}
+
+impl Wave {
+    fn new() -> Self {
+        Self {
+            length: Length::new(256),
+            ..Default::default()
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    fn tick(&mut self) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        self.timer -= 1;
+        if self.timer > 0 {
+            return false;
+        }
+
+        self.timer = self.period();
+        self.position = (self.position + 1) % 32;
+        self.sample_byte = self.ram[self.position as usize / 2];
+        true
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let sample = if self.position.is_multiple_of(2) {
+            self.sample_byte >> 4
+        } else {
+            self.sample_byte & 0x0F
+        };
+
+        match self.volume {
+            0 => 0,
+            1 => sample,
+            2 => sample >> 1,
+            _ => sample >> 2,
+        }
+    }
+
+    fn write(&mut self, register: u16, value: u8, extra_clock: bool) {
+        match register {
+            0 => {
+                self.dac_enabled = value & 0x80 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(value),
+            2 => self.volume = (value >> 5) & 0x03,
+            3 => self.frequency = (self.frequency & 0x700) | value as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | ((value as u16 & 0x07) << 8);
+                if self.length.write_enable(value & 0x40 != 0, extra_clock) {
+                    self.enabled = false;
+                }
+                if value & 0x80 != 0 {
+                    self.enabled = self.dac_enabled;
+                    self.length.trigger(extra_clock);
+                    self.position = 0;
+                    // the first sample is fetched a few cycles late
+                    self.timer = self.period() + 6;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+// channel 4, pseudo-random noise from a linear feedback shift register
+#[derive(Debug, Default)]
+struct Noise {
+    enabled: bool,
+    length: Length,
+    envelope: Envelope,
+    nr43: u8,
+    lfsr: u16,
+    timer: u32,
+}
+
+impl Noise {
+    fn new() -> Self {
+        Self {
+            length: Length::new(64),
+            ..Default::default()
+        }
+    }
+
+    fn period(&self) -> u32 {
+        let divisor = match self.nr43 & 0x07 {
+            0 => 8,
+            r => r as u32 * 16,
+        };
+
+        divisor << (self.nr43 >> 4)
+    }
+
+    fn tick(&mut self) -> bool {
+        // shifts of 14 and 15 stop the LFSR
+        if !self.enabled || self.nr43 >> 4 >= 14 {
+            return false;
+        }
+
+        self.timer -= 1;
+        if self.timer > 0 {
+            return false;
+        }
+
+        self.timer = self.period();
+
+        let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+        // 7-bit mode also feeds back into bit 6
+        if self.nr43 & 0x08 != 0 {
+            self.lfsr = (self.lfsr & !(1 << 6)) | (feedback << 6);
+        }
+        true
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && self.lfsr & 1 == 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+
+    fn write(&mut self, register: u16, value: u8, extra_clock: bool) {
+        match register {
+            0 => {}
+            1 => self.length.load(value & 0x3F),
+            2 => {
+                self.envelope.write(value);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.nr43 = value,
+            4 => {
+                if self.length.write_enable(value & 0x40 != 0, extra_clock) {
+                    self.enabled = false;
+                }
+                if value & 0x80 != 0 {
+                    self.enabled = self.envelope.dac_enabled();
+                    self.length.trigger(extra_clock);
+                    self.envelope.trigger();
+                    self.lfsr = 0x7FFF;
+                    self.timer = self.period();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+pub struct Apu {
+    model: Model,
+    power: bool,
+
+    ch1: Square,
+    ch2: Square,
+    ch3: Wave,
+    ch4: Noise,
+
+    // NR10-NR51 as last written, reads OR in READ_MASKS
+    registers: [u8; 0x16],
+
+    // next step of the frame sequencer (0-7)
+    frame_step: u8,
+    div_bit: bool,
+
+    // the mixer's output as of the last change, fed into the blip
+    // buffers as deltas
+    sample_rate: u32,
+    output: (f32, f32),
+    left: BlipBuffer,
+    right: BlipBuffer,
+
+    high_pass: HighPass,
+    charge_factor: Option<f32>,
+    capacitors: [f32; 2], // left, right
+
+    channel_capture: Option<ChannelCapture>,
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self::with_model(Model::Dmg)
+    }
+
+    pub fn with_model(model: Model) -> Self {
+        let high_pass = match model {
+            Model::Dmg => HighPass::Dmg,
+            Model::Cgb => HighPass::Cgb,
+        };
+
+        Self {
+            model,
+            power: false,
+            ch1: Square::new(true),
+            ch2: Square::new(false),
+            ch3: Wave::new(),
+            ch4: Noise::new(),
+            registers: [0; 0x16],
+            frame_step: 0,
+            div_bit: false,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            output: (0.0, 0.0),
+            left: BlipBuffer::new(CLOCK_RATE, DEFAULT_SAMPLE_RATE),
+            right: BlipBuffer::new(CLOCK_RATE, DEFAULT_SAMPLE_RATE),
+            high_pass,
+            charge_factor: high_pass.charge_factor(DEFAULT_SAMPLE_RATE),
+            capacitors: [0.0; 2],
+            channel_capture: None,
+        }
+    }
+
+    // drops any samples not taken yet
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.left = BlipBuffer::new(CLOCK_RATE, sample_rate);
+        self.right = BlipBuffer::new(CLOCK_RATE, sample_rate);
+        // the buffers start from silence again
+        self.output = (0.0, 0.0);
+        self.charge_factor = self.high_pass.charge_factor(sample_rate);
+
+        if self.channel_capture.is_some() {
+            self.channel_capture = Some(ChannelCapture::new(sample_rate));
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_high_pass(&mut self, high_pass: HighPass) {
+        self.high_pass = high_pass;
+        self.charge_factor = high_pass.charge_factor(self.sample_rate);
+        self.capacitors = [0.0; 2];
+    }
+
+    // also keep every channel's output on its own, see AudioSink
+    pub fn set_channel_capture(&mut self, enabled: bool) {
+        self.channel_capture = enabled.then(|| ChannelCapture::new(self.sample_rate));
+    }
+
+    // advances by `cycles` T-cycles, `div` is the timer's internal counter
+    pub fn tick(&mut self, cycles: u8, div: u16) {
+        let div_bit = div & FRAME_SEQUENCER_BIT != 0;
+        if self.div_bit && !div_bit && self.power {
+            self.step_frame_sequencer();
+        }
+        self.div_bit = div_bit;
+
+        // register writes since the last tick can change the output too
+        self.update_output();
+
+        for _ in 0..cycles {
+            if self.power {
+                // no short-circuiting, every channel has to run
+                let changed = self.ch1.tick() | self.ch2.tick() | self.ch3.tick() | self.ch4.tick();
+                if changed {
+                    self.update_output();
+                }
+            }
+
+            self.left.advance(1);
+            self.right.advance(1);
+            if let Some(capture) = self.channel_capture.as_mut() {
+                capture.buffers.iter_mut().for_each(|b| b.advance(1));
+            }
+        }
+    }
+
+    // records a change of the mixer's output at the current cycle
+    fn update_output(&mut self) {
+        let outputs = self.channel_outputs();
+        let (left, right) = self.mix(&outputs);
+
+        if left != self.output.0 {
+            self.left.add_delta(left - self.output.0);
+        }
+        if right != self.output.1 {
+            self.right.add_delta(right - self.output.1);
+        }
+
+        self.output = (left, right);
+
+        if let Some(capture) = self.channel_capture.as_mut() {
+            let channels = capture.buffers.iter_mut().zip(&capture.output);
+            for ((buffer, &last), &output) in channels.zip(&outputs) {
+                if output != last {
+                    buffer.add_delta(output - last);
+                }
+            }
+            capture.output = outputs;
+        }
+    }
+
+    fn step_frame_sequencer(&mut self) {
+        // length on every other step, sweep on 2 and 6, envelopes on 7
+        if self.frame_step.is_multiple_of(2) {
+            if self.ch1.length.clock() {
+                self.ch1.enabled = false;
+            }
+            if self.ch2.length.clock() {
+                self.ch2.enabled = false;
+            }
+            if self.ch3.length.clock() {
+                self.ch3.enabled = false;
+            }
+            if self.ch4.length.clock() {
+                self.ch4.enabled = false;
+            }
+        }
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.ch1.clock_sweep();
+        }
+        if self.frame_step == 7 {
+            self.ch1.envelope.clock();
+            self.ch2.envelope.clock();
+            self.ch4.envelope.clock();
+        }
+
+        self.frame_step = (self.frame_step + 1) % 8;
+    }
+
+    // true when the next frame sequencer step doesn't clock length
+    fn extra_length_clock(&self) -> bool {
+        self.frame_step % 2 == 1
+    }
+
+    // every channel through its DAC, -1.0 to 1.0, or 0.0 with the DAC off
+    fn channel_outputs(&self) -> [f32; 4] {
+        let dac = |enabled: bool, digital: u8| {
+            if enabled {
+                digital as f32 / 7.5 - 1.0
+            } else {
+                0.0
+            }
+        };
+
+        [
+            dac(self.ch1.envelope.dac_enabled(), self.ch1.output()),
+            dac(self.ch2.envelope.dac_enabled(), self.ch2.output()),
+            dac(self.ch3.dac_enabled, self.ch3.output()),
+            dac(self.ch4.envelope.dac_enabled(), self.ch4.output()),
+        ]
+    }
+
+    // NR51 routes channels to each side, NR50 scales each side by 1/8 to 8/8
+    fn mix(&self, outputs: &[f32; 4]) -> (f32, f32) {
+        if !self.power {
+            return (0.0, 0.0);
+        }
+
+        let nr50 = self.registers[0x14];
+        let nr51 = self.registers[0x15];
+
+        let side = |enables: u8, volume: u8| {
+            let sum: f32 = (0..4)
+                .filter(|i| enables & (1 << i) != 0)
+                .map(|i| outputs[i])
+                .sum();
+            sum / 4.0 * (volume + 1) as f32 / 8.0
+        };
+
+        (
+            side(nr51 >> 4, (nr50 >> 4) & 0x07),
+            side(nr51 & 0x0F, nr50 & 0x07),
+        )
+    }
+
+    // interleaved left/right samples produced since the last call
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        let count = self.left.samples_available();
+        let mut left = Vec::with_capacity(count);
+        let mut right = Vec::with_capacity(count);
+        self.left.read_samples(count, &mut left);
+        self.right.read_samples(count, &mut right);
+
+        let mut samples = Vec::with_capacity(count * 2);
+        for (l, r) in left.into_iter().zip(right) {
+            samples.push(high_pass(l, &mut self.capacitors[0], self.charge_factor));
+            samples.push(high_pass(r, &mut self.capacitors[1], self.charge_factor));
+        }
+
+        samples
+    }
+
+    // each channel's samples since the last call, empty unless capturing
+    pub fn take_channel_samples(&mut self) -> [Vec<f32>; 4] {
+        let Some(capture) = self.channel_capture.as_mut() else {
+            return Default::default();
+        };
+
+        std::array::from_fn(|i| {
+            let buffer = &mut capture.buffers[i];
+            let mut samples = Vec::with_capacity(buffer.samples_available());
+            buffer.read_samples(buffer.samples_available(), &mut samples);
+
+            for sample in samples.iter_mut() {
+                *sample = high_pass(*sample, &mut capture.capacitors[i], self.charge_factor);
+            }
+            samples
+        })
+    }
+
+    // hands everything produced since the last call to `sink`
+    pub fn write_to(&mut self, sink: &mut impl AudioSink) -> io::Result<()> {
+        sink.write_samples(&self.take_samples())?;
+        if self.channel_capture.is_some() {
+            sink.write_channel_samples(&self.take_channel_samples())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn take_samples_i16(&mut self) -> Vec<i16> {
+        self.take_samples()
+            .into_iter()
+            .map(|s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+            .collect()
+    }
+
+    // while channel 3 plays, wave RAM accesses land on the byte it's
+    // reading. The DMG only allows that on the exact cycle of a fetch,
+    // which isn't tracked here, so it's treated as never
+    fn read_wave_ram(&self, addr: u16) -> u8 {
+        if !self.ch3.enabled {
+            self.ch3.ram[(addr - 0xFF30) as usize]
+        } else if self.model.is_cgb() {
+            self.ch3.ram[self.ch3.position as usize / 2]
+        } else {
+            0xFF
+        }
+    }
+
+    fn write_wave_ram(&mut self, addr: u16, value: u8) {
+        if !self.ch3.enabled {
+            self.ch3.ram[(addr - 0xFF30) as usize] = value;
+        } else if self.model.is_cgb() {
+            self.ch3.ram[self.ch3.position as usize / 2] = value;
+        }
+    }
+
+    // clears every register, wave RAM is kept and so are the
+    // length counters on DMG
+    fn power_off(&mut self) {
+        let lengths = [
+            self.ch1.length.counter,
+            self.ch2.length.counter,
+            self.ch3.length.counter,
+            self.ch4.length.counter,
+        ];
+        let ram = self.ch3.ram;
+
+        self.ch1 = Square::new(true);
+        self.ch2 = Square::new(false);
+        self.ch3 = Wave::new();
+        self.ch4 = Noise::new();
+        self.ch3.ram = ram;
+        self.registers = [0; 0x16];
+
+        if !self.model.is_cgb() {
+            self.ch1.length.counter = lengths[0];
+            self.ch2.length.counter = lengths[1];
+            self.ch3.length.counter = lengths[2];
+            self.ch4.length.counter = lengths[3];
+        }
+    }
+}
+
+impl MemoryMappedDevice for Apu {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF10..=0xFF25 => {
+                self.registers[(addr - 0xFF10) as usize] | READ_MASKS[(addr - 0xFF10) as usize]
+            }
+
+            0xFF26 => {
+                let channels = [
+                    self.ch1.enabled,
+                    self.ch2.enabled,
+                    self.ch3.enabled,
+                    self.ch4.enabled,
+                ];
+                let status = channels
+                    .iter()
+                    .enumerate()
+                    .fold(0, |acc, (i, &on)| acc | (on as u8) << i);
+
+                (self.power as u8) << 7 | READ_MASKS[0x16] | status
+            }
+
+            0xFF27..=0xFF2F => 0xFF,
+
+            0xFF30..=0xFF3F => self.read_wave_ram(addr),
+
+            _ => unreachable!("Not an APU register: 0x{:04X}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0xFF26 => {
+                let power = value & 0x80 != 0;
+                if self.power && !power {
+                    self.power_off();
+                } else if !self.power && power {
+                    // the frame sequencer starts over, so the next step is 0
+                    self.frame_step = 0;
+                }
+                self.power = power;
+            }
+
+            0xFF30..=0xFF3F => self.write_wave_ram(addr, value),
+
+            // powered off, only the DMG's length counters can still be written
+            0xFF10..=0xFF25 if !self.power => {
+                if !self.model.is_cgb() {
+                    match addr {
+                        0xFF11 => self.ch1.length.load(value & 0x3F),
+                        0xFF16 => self.ch2.length.load(value & 0x3F),
+                        0xFF1B => self.ch3.length.load(value),
+                        0xFF20 => self.ch4.length.load(value & 0x3F),
+                        _ => {}
+                    }
+                }
+            }
+
+            0xFF10..=0xFF25 => {
+                self.registers[(addr - 0xFF10) as usize] = value;
+
+                let extra_clock = self.extra_length_clock();
+                match addr {
+                    0xFF10..=0xFF14 => self.ch1.write(addr - 0xFF10, value, extra_clock),
+                    0xFF15..=0xFF19 => self.ch2.write(addr - 0xFF15, value, extra_clock),
+                    0xFF1A..=0xFF1E => self.ch3.write(addr - 0xFF1A, value, extra_clock),
+                    0xFF1F..=0xFF23 => self.ch4.write(addr - 0xFF1F, value, extra_clock),
+                    // NR50 and NR51 are only used by the mixer
+                    _ => {}
+                }
+            }
+
+            0xFF27..=0xFF2F => {}
+
+            _ => unreachable!("Not an APU register: 0x{:04X}", addr),
+        }
+    }
+
+    // the frame sequencer follows bit 12 of the divider, bit 13 in
+    // double speed, which keeps it at 512 Hz either way
+    fn tick(&mut self, clock: &Clock) -> u8 {
+        let div = (clock.div as u16) << 8 >> clock.double_speed as u16;
+        Apu::tick(self, clock.real_cycles, div);
+        0
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn powered(model: Model) -> Apu {
+        let mut apu = Apu::with_model(model);
+        apu.write(0xFF26, 0x80);
+        apu
+    }
+
+    // runs the frame sequencer through `steps` falling edges of DIV bit 12
+    fn step_frame_sequencer(apu: &mut Apu, steps: usize) {
+        for _ in 0..steps {
+            apu.tick(0, FRAME_SEQUENCER_BIT);
+            apu.tick(0, 0);
+        }
+    }
+
+    fn channel_on(apu: &Apu, channel: u8) -> bool {
+        apu.read(0xFF26) & (1 << (channel - 1)) != 0
+    }
+
+    #[test]
+    fn frame_sequencer_clocks_length_sweep_and_envelope_on_their_steps() {
+        let mut apu = powered(Model::Dmg);
+        // channel 1 sweeping up by half its frequency every sweep clock
+        apu.write(0xFF10, 0x11);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF13, 0x00);
+        apu.write(0xFF14, 0x81);
+        // channel 2 with length on and the envelope going up every clock
+        apu.write(0xFF17, 0x09);
+        apu.write(0xFF19, 0xC0);
+
+        let mut clocked = Vec::new();
+        for _ in 0..8 {
+            let before = (
+                apu.ch2.length.counter,
+                apu.ch1.frequency,
+                apu.ch2.envelope.volume,
+            );
+            step_frame_sequencer(&mut apu, 1);
+            clocked.push((
+                apu.ch2.length.counter != before.0,
+                apu.ch1.frequency != before.1,
+                apu.ch2.envelope.volume != before.2,
+            ));
+        }
+
+        // length, sweep, envelope
+        let expected = [
+            (true, false, false),
+            (false, false, false),
+            (true, true, false),
+            (false, false, false),
+            (true, false, false),
+            (false, false, false),
+            (true, true, false),
+            (false, false, true),
+        ];
+        assert_eq!(clocked, expected);
+        assert_eq!(apu.ch2.length.counter, 60);
+        assert_eq!(apu.ch1.frequency, 0x240);
+        assert_eq!(apu.ch2.envelope.volume, 1);
+    }
+
+    #[test]
+    fn sweep_overflow_disables_channel_1() {
+        let mut apu = powered(Model::Dmg);
+        apu.write(0xFF12, 0xF0);
+
+        // checked as soon as it's triggered
+        apu.write(0xFF10, 0x11);
+        apu.write(0xFF13, 0xFF);
+        apu.write(0xFF14, 0x87);
+        assert!(!channel_on(&apu, 1));
+
+        // 0x500 sweeps to 0x780, which passes, then the check of the next
+        // one, 0xB40, doesn't
+        apu.write(0xFF13, 0x00);
+        apu.write(0xFF14, 0x85);
+        assert!(channel_on(&apu, 1));
+        step_frame_sequencer(&mut apu, 2);
+        assert!(channel_on(&apu, 1));
+        step_frame_sequencer(&mut apu, 1);
+        assert!(!channel_on(&apu, 1));
+        assert_eq!(apu.ch1.frequency, 0x780);
+
+        // sweeping down never overflows, but leaving negate mode after a
+        // calculation does the same
+        apu.write(0xFF10, 0x19);
+        apu.write(0xFF14, 0x87);
+        assert!(channel_on(&apu, 1));
+        apu.write(0xFF10, 0x11);
+        assert!(!channel_on(&apu, 1));
+    }
+
+    #[test]
+    fn length_counter_silences_the_channel() {
+        let mut apu = powered(Model::Dmg);
+        apu.write(0xFF16, 0x3E); // 2 steps
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF19, 0xC0);
+
+        step_frame_sequencer(&mut apu, 2);
+        assert!(channel_on(&apu, 2));
+        step_frame_sequencer(&mut apu, 1);
+        assert!(!channel_on(&apu, 2));
+
+        // enabling length when the next step won't clock it clocks it once
+        assert_eq!(apu.frame_step, 3);
+        apu.write(0xFF16, 0x3E);
+        apu.write(0xFF19, 0x80);
+        apu.write(0xFF19, 0x40);
+        assert_eq!(apu.ch2.length.counter, 1);
+        step_frame_sequencer(&mut apu, 1);
+        assert!(channel_on(&apu, 2));
+        step_frame_sequencer(&mut apu, 1);
+        assert!(!channel_on(&apu, 2));
+
+        // the wave channel counts 256
+        apu.write(0xFF1A, 0x80);
+        apu.write(0xFF1B, 0x00);
+        apu.write(0xFF1E, 0x80);
+        assert_eq!(apu.ch3.length.counter, 256);
+    }
+
+    #[test]
+    fn envelope_stops_at_0_and_15() {
+        let mut apu = powered(Model::Dmg);
+        apu.write(0xFF17, 0xE9);
+        apu.write(0xFF19, 0x80);
+        step_frame_sequencer(&mut apu, 16);
+        assert_eq!(apu.ch2.envelope.volume, 15);
+
+        apu.write(0xFF17, 0x11);
+        apu.write(0xFF19, 0x80);
+        step_frame_sequencer(&mut apu, 16);
+        assert_eq!(apu.ch2.envelope.volume, 0);
+        // the DAC is still on, so the channel is too
+        assert!(channel_on(&apu, 2));
+
+        // a period of 0 doesn't move it
+        apu.write(0xFF17, 0x80);
+        apu.write(0xFF19, 0x80);
+        step_frame_sequencer(&mut apu, 16);
+        assert_eq!(apu.ch2.envelope.volume, 8);
+    }
+
+    #[test]
+    fn powering_off_clears_the_registers() {
+        for model in [Model::Dmg, Model::Cgb] {
+            let mut apu = powered(model);
+            apu.write(0xFF30, 0x12);
+            for addr in 0xFF10..=0xFF25 {
+                apu.write(addr, 0xFF);
+            }
+
+            apu.write(0xFF26, 0x00);
+            for addr in 0xFF10..=0xFF25 {
+                let mask = READ_MASKS[(addr - 0xFF10) as usize];
+                assert_eq!(apu.read(addr), mask, "{model:?} 0x{addr:04X}");
+            }
+            assert_eq!(apu.read(0xFF26), 0x70);
+            assert_eq!(apu.read(0xFF30), 0x12);
+
+            // and ignores writes, except to the DMG's length counters
+            apu.write(0xFF24, 0x77);
+            apu.write(0xFF11, 0x3F);
+            assert_eq!(apu.read(0xFF24), 0x00);
+            let expected = if model.is_cgb() { 0 } else { 1 };
+            assert_eq!(apu.ch1.length.counter, expected, "{model:?}");
+
+            // back on, the frame sequencer starts from step 0
+            apu.write(0xFF26, 0x80);
+            assert_eq!(apu.frame_step, 0);
+            apu.write(0xFF24, 0x77);
+            assert_eq!(apu.read(0xFF24), 0x77);
+        }
+    }
+
+    #[test]
+    fn square_duty_cycles() {
+        for (duty, high) in [(0, 1), (1, 2), (2, 4), (3, 6)] {
+            let mut apu = powered(Model::Dmg);
+            apu.write(0xFF16, duty << 6);
+            apu.write(0xFF17, 0xF0);
+            // the highest frequency, 4 T-cycles a step
+            apu.write(0xFF18, 0xFF);
+            apu.write(0xFF19, 0x87);
+
+            let mut outputs = Vec::new();
+            for _ in 0..8 {
+                apu.tick(4, 0);
+                outputs.push(apu.ch2.output());
+            }
+            assert_eq!(outputs.iter().filter(|&&o| o == 15).count(), high);
+            assert_eq!(outputs.iter().filter(|&&o| o == 0).count(), 8 - high);
+        }
+    }
+
+    #[test]
+    fn wave_channel_plays_wave_ram_at_its_volume() {
+        for (nr32, shift) in [(0x20, 0), (0x40, 1), (0x60, 2)] {
+            let mut apu = powered(Model::Dmg);
+            // samples counting up from 0 to 15, twice
+            for i in 0..16 {
+                let sample = (i as u8 * 2) & 0x0F;
+                apu.write(0xFF30 + i, sample << 4 | (sample + 1));
+            }
+            apu.write(0xFF1A, 0x80);
+            apu.write(0xFF1C, nr32);
+            // 2 T-cycles a sample, the first one fetched 6 late
+            apu.write(0xFF1D, 0xFF);
+            apu.write(0xFF1E, 0x87);
+
+            apu.tick(8, 0);
+            let mut outputs = vec![apu.ch3.output()];
+            for _ in 0..31 {
+                apu.tick(2, 0);
+                outputs.push(apu.ch3.output());
+            }
+
+            let expected: Vec<u8> = (1..32).chain([0]).map(|s| (s & 0x0F) >> shift).collect();
+            assert_eq!(outputs, expected, "NR32=0x{nr32:02X}");
+        }
+    }
+
+    // the LFSR's output bit over `count` shifts
+    fn noise_bits(nr43: u8, count: usize) -> Vec<u8> {
+        let mut apu = powered(Model::Dmg);
+        apu.write(0xFF21, 0xF0);
+        apu.write(0xFF22, nr43);
+        apu.write(0xFF23, 0x80);
+
+        (0..count)
+            .map(|_| {
+                while !apu.ch4.tick() {}
+                apu.ch4.output() / 15
+            })
+            .collect()
+    }
+
+    #[test]
+    fn noise_repeats_every_127_or_32767_shifts() {
+        let bits = noise_bits(0x08, 400);
+        assert!(bits[..127].contains(&0) && bits[..127].contains(&1));
+        assert_eq!(bits[..273], bits[127..]);
+
+        let bits = noise_bits(0x00, 32767 + 400);
+        assert_ne!(bits[..400], bits[127..527]);
+        assert_eq!(bits[..400], bits[32767..]);
+    }
+
+    #[test]
+    fn dac_off_disables_the_channel() {
+        let mut apu = powered(Model::Dmg);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF14, 0x80);
+        assert!(channel_on(&apu, 1));
+        apu.write(0xFF12, 0x00);
+        assert!(!channel_on(&apu, 1));
+
+        // triggering doesn't turn it back on
+        apu.write(0xFF14, 0x80);
+        assert!(!channel_on(&apu, 1));
+        assert_eq!(apu.channel_outputs()[0], 0.0);
+    }
+
+    #[test]
+    fn nr50_and_nr51_route_and_scale_each_side() {
+        let mut apu = powered(Model::Dmg);
+        // channel 2 on the left at full volume, channel 1 on the right at 1/8
+        apu.write(0xFF25, 0x21);
+        apu.write(0xFF24, 0x70);
+
+        assert_eq!(apu.mix(&[1.0, -1.0, 0.5, 0.0]), (-0.25, 0.03125));
+
+        apu.write(0xFF26, 0x00);
+        assert_eq!(apu.mix(&[1.0, -1.0, 0.5, 0.0]), (0.0, 0.0));
+    }
+
+    // a constant input through the filter, one output per sample
+    fn filtered(mode: HighPass, count: usize) -> Vec<f32> {
+        let charge_factor = mode.charge_factor(DEFAULT_SAMPLE_RATE);
+        let mut capacitor = 0.0;
+        (0..count)
+            .map(|_| high_pass(1.0, &mut capacitor, charge_factor))
+            .collect()
+    }
+
+    #[test]
+    fn high_pass_takes_out_dc_faster_on_cgb() {
+        assert!(filtered(HighPass::Off, 1000).iter().all(|&s| s == 1.0));
+
+        let dmg = filtered(HighPass::Dmg, DEFAULT_SAMPLE_RATE as usize);
+        let cgb = filtered(HighPass::Cgb, DEFAULT_SAMPLE_RATE as usize);
+        assert_eq!((dmg[0], cgb[0]), (1.0, 1.0));
+        assert!(dmg[100] > 0.5 && dmg[100] < 0.9);
+        assert!(cgb[100] < 0.01);
+        // down to what f32 can resolve
+        assert!(dmg.last().unwrap().abs() < 1e-4);
+        assert!(cgb.last().unwrap().abs() < 1e-4);
+
+        // at the clock rate the factor is the per-cycle one
+        assert_eq!(HighPass::Dmg.charge_factor(CLOCK_RATE), Some(0.999958));
+    }
+
+    // an eighth of a second of an APU with channel 2's DAC on but silent,
+    // which sits at -1.0 and mixes to -0.25 on both sides
+    fn dc_offset(high_pass: HighPass) -> Vec<f32> {
+        let mut apu = powered(Model::Dmg);
+        apu.set_high_pass(high_pass);
+        apu.write(0xFF24, 0x77);
+        apu.write(0xFF25, 0x22);
+        apu.write(0xFF17, 0x08);
+
+        for _ in 0..CLOCK_RATE / 32 {
+            apu.tick(4, 0);
+        }
+        apu.take_samples()
+    }
+
+    #[test]
+    fn apu_output_rate_and_high_pass_modes() {
+        let samples = dc_offset(HighPass::Off);
+        assert_eq!(samples.len(), 2 * DEFAULT_SAMPLE_RATE as usize / 8);
+        let last = &samples[samples.len() - 2..];
+        assert!(last.iter().all(|s| (s + 0.25).abs() < 1e-5));
+
+        for high_pass in [HighPass::Dmg, HighPass::Cgb] {
+            let samples = dc_offset(high_pass);
+            let last = &samples[samples.len() - 2..];
+            assert!(last.iter().all(|s| s.abs() < 1e-4), "{high_pass:?}");
+        }
+    }
+}
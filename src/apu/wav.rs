@@ -0,0 +1,217 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use super::AudioSink;
+
+// 16-bit PCM .wav file. The sizes in the header are only filled in by
+// `finish`, until then they're 0
+pub struct WavWriter {
+    file: BufWriter<File>,
+    channels: u16,
+    data_len: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        let mut writer = Self {
+            file: BufWriter::new(File::create(path)?),
+            channels,
+            data_len: 0,
+        };
+        writer.write_header(sample_rate)?;
+
+        Ok(writer)
+    }
+
+    fn write_header(&mut self, sample_rate: u32) -> io::Result<()> {
+        let block_align = self.channels * 2;
+
+        self.file.write_all(b"RIFF")?;
+        self.file.write_all(&0u32.to_le_bytes())?; // patched by finish
+        self.file.write_all(b"WAVE")?;
+
+        self.file.write_all(b"fmt ")?;
+        self.file.write_all(&16u32.to_le_bytes())?;
+        self.file.write_all(&1u16.to_le_bytes())?; // PCM
+        self.file.write_all(&self.channels.to_le_bytes())?;
+        self.file.write_all(&sample_rate.to_le_bytes())?;
+        self.file
+            .write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        self.file.write_all(&block_align.to_le_bytes())?;
+        self.file.write_all(&16u16.to_le_bytes())?; // bits per sample
+
+        self.file.write_all(b"data")?;
+        self.file.write_all(&0u32.to_le_bytes())?; // patched by finish
+
+        Ok(())
+    }
+
+    // samples are interleaved when there's more than one channel
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        for &sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_len += samples.len() as u32 * 2;
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(36 + self.data_len).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&self.data_len.to_le_bytes())?;
+        self.file.flush()
+    }
+}
+
+// a full scale signal overshoots 1.0 once it's been through the high-pass
+// filter, so everything going into the files is scaled down by this to
+// leave some headroom
+const HEADROOM: f32 = 0.5;
+
+// the mixed output as a stereo .wav, plus a mono .wav per channel next
+// to it when channel capture is on
+pub struct WavSink {
+    mixed: WavWriter,
+    channels: Option<[WavWriter; 4]>,
+}
+
+impl WavSink {
+    // channel files get "-ch1" to "-ch4" added to `path`'s name
+    pub fn create(path: &Path, sample_rate: u32, channels: bool) -> io::Result<Self> {
+        let mixed = WavWriter::create(path, sample_rate, 2)?;
+
+        let channels = if channels {
+            let writers =
+                [1, 2, 3, 4].map(|n| WavWriter::create(&channel_path(path, n), sample_rate, 1));
+            let [a, b, c, d] = writers;
+            Some([a?, b?, c?, d?])
+        } else {
+            None
+        };
+
+        Ok(Self { mixed, channels })
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.mixed.finish()?;
+        for writer in self.channels.into_iter().flatten() {
+            writer.finish()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl AudioSink for WavSink {
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        write_scaled(&mut self.mixed, samples)
+    }
+
+    fn write_channel_samples(&mut self, channels: &[Vec<f32>; 4]) -> io::Result<()> {
+        let Some(writers) = self.channels.as_mut() else {
+            return Ok(());
+        };
+
+        for (writer, samples) in writers.iter_mut().zip(channels) {
+            write_scaled(writer, samples)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_scaled(writer: &mut WavWriter, samples: &[f32]) -> io::Result<()> {
+    let samples: Vec<f32> = samples.iter().map(|s| s * HEADROOM).collect();
+    writer.write_samples(&samples)
+}
+
+fn channel_path(path: &Path, channel: u8) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{stem}-ch{channel}.wav"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("gb-wav-test-{}-{name}.wav", std::process::id()))
+    }
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    // the samples after the 44 byte header, and deletes the file
+    fn take_data(path: &Path) -> Vec<i16> {
+        let bytes = fs::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(u32_at(&bytes, 40) as usize, bytes.len() - 44);
+        bytes[44..]
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn finish_fills_in_the_header() {
+        let path = temp_path("header");
+        let mut writer = WavWriter::create(&path, 44_100, 2).unwrap();
+        writer.write_samples(&[0.0, 1.0, -1.0, 0.5]).unwrap();
+        writer.write_samples(&[2.0, -2.0]).unwrap();
+        writer.finish().unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), 44 + 12);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32_at(&bytes, 4), 36 + 12);
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&bytes, 16), 16);
+        assert_eq!(u16_at(&bytes, 20), 1);
+        assert_eq!(u16_at(&bytes, 22), 2);
+        assert_eq!(u32_at(&bytes, 24), 44_100);
+        assert_eq!(u32_at(&bytes, 28), 44_100 * 4);
+        assert_eq!(u16_at(&bytes, 32), 4);
+        assert_eq!(u16_at(&bytes, 34), 16);
+        assert_eq!(&bytes[36..40], b"data");
+
+        // out of range samples are clamped
+        assert_eq!(
+            take_data(&path),
+            [0, i16::MAX, -i16::MAX, i16::MAX / 2, i16::MAX, -i16::MAX]
+        );
+    }
+
+    #[test]
+    fn sink_scales_the_mix_and_the_channels_alike() {
+        let path = temp_path("sink");
+        let mut sink = WavSink::create(&path, 48_000, true).unwrap();
+        sink.write_samples(&[1.0, -1.0, 0.5, 0.0]).unwrap();
+        let channels = [vec![1.0], vec![-1.0], vec![0.5], vec![0.0]];
+        sink.write_channel_samples(&channels).unwrap();
+        sink.finish().unwrap();
+
+        let scaled = |s: f32| (s * HEADROOM * i16::MAX as f32) as i16;
+        let expected: Vec<i16> = [1.0, -1.0, 0.5, 0.0].map(scaled).to_vec();
+        assert_eq!(take_data(&path), expected);
+        for (n, samples) in (1..=4).zip(channels) {
+            assert_eq!(take_data(&channel_path(&path, n)), [scaled(samples[0])]);
+        }
+    }
+
+    #[test]
+    fn channel_files_go_next_to_the_mix() {
+        let path = channel_path(Path::new("out/game.wav"), 3);
+        assert_eq!(path, Path::new("out/game-ch3.wav"));
+    }
+}
@@ -1,40 +1,679 @@
+use std::any::Any;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::apu::Apu;
+use crate::cartridge::Cartridge;
+use crate::cpu::MemoryInterface;
+use crate::dma::{Hdma, OamDma};
+use crate::interrupts::Interrupt;
+use crate::joypad::Joypad;
+use crate::mmu::Mmu;
+use crate::model::Model;
+use crate::ppu::Ppu;
+use crate::serial::Serial;
+use crate::timer::Timer;
+
+// CPU T-cycles the CPU sits still for after STOP switches speed
+const SPEED_SWITCH_CYCLES: u16 = 8200;
+
+// OAM, the unusable region, I/O and HRAM share pages, so from here up
+// the address table has an entry per byte
+const HIGH_START: u16 = 0xFE00;
+
+// what a device is told each time the bus ticks it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Clock {
+    pub cycles: u8,      // T-cycles of the CPU clock
+    pub real_cycles: u8, // the same in real time, half as many in double speed
+    pub double_speed: bool,
+    // DIV as the CPU would read it before this tick, and whether STOP or a
+    // speed switch is holding it still
+    pub div: u8,
+    pub div_held: bool,
+}
+
+// anything that answers to a range of addresses on the bus. The built in
+// components are attached through this as well, so any of them can be
+// replaced by attaching something else over its range
+pub trait MemoryMappedDevice: Any {
+    fn read(&self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, value: u8);
+
+    // advances by one tick of the bus, returns the IF bits of any
+    // interrupts requested
+    fn tick(&mut self, _clock: &Clock) -> u8 {
+        0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttachError {
+    // below 0xFE00 the address table works in 256 byte pages
+    PartialPage { start: u16, end: u16 },
+}
+
+impl fmt::Display for AttachError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AttachError::PartialPage { start, end } => {
+                write!(f, "0x{start:04X}-0x{end:04X} doesn't cover whole pages")
+            }
+        }
+    }
+}
+
+impl std::error::Error for AttachError {}
+
+// who answers an address
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Slot {
+    Bus,
+    Device(usize), // index into Bus::devices
+    Unmapped,
+}
+
 pub struct Bus {
-    pub memory: [u8; 0x10000],
+    pub model: Model,
+    pub oam_dma: OamDma,
+    // the byte OAM DMA last read, what the CPU sees on a bus conflict
+    oam_dma_byte: u8,
+    pub hdma: Hdma,
+    // the PPU mode in STAT after the last tick, HBlank DMA watches it
+    lcd_mode: u8,
+
+    // interrupt registers
+    pub interrupt_enable: u8, // IE (0xFFFF)
+    pub interrupt_flag: u8,   // IF (0xFF0F)
 
-    // serial debug support
-    serial_data: u8,
+    // CGB speed switch (KEY1, 0xFF4D)
+    pub double_speed: bool,
+    speed_switch_armed: bool,
+    // cycles left until the CPU runs again after a switch
+    speed_switch_cycles: u16,
+    // the CPU executed STOP and is waiting for the joypad
+    stopped: bool,
+
+    // read/write dispatch, 256 byte pages up to 0xFE00 and single bytes after
+    pages: [Slot; (HIGH_START >> 8) as usize],
+    high_slots: [Slot; 0x10000 - HIGH_START as usize],
+    // ticked in the order they were attached
+    devices: Vec<Box<dyn MemoryMappedDevice>>,
 }
 
 impl Bus {
     pub fn new() -> Self {
-        Self {
-            memory: [0; 0x10000],
-            serial_data: 0,
+        Self::with_model(Model::Dmg)
+    }
+
+    pub fn with_model(model: Model) -> Self {
+        let mut bus = Self {
+            model,
+            oam_dma: OamDma::new(),
+            oam_dma_byte: 0xFF,
+            hdma: Hdma::new(),
+            lcd_mode: 0,
+            interrupt_enable: 0,
+            interrupt_flag: 0,
+            double_speed: false,
+            speed_switch_armed: false,
+            speed_switch_cycles: 0,
+            stopped: false,
+            pages: [Slot::Unmapped; (HIGH_START >> 8) as usize],
+            high_slots: [Slot::Unmapped; 0x10000 - HIGH_START as usize],
+            devices: Vec::new(),
+        };
+
+        bus.map(0xFF0F..=0xFF0F, Slot::Bus);
+        bus.map(0xFF46..=0xFF46, Slot::Bus);
+        bus.map(0xFFFF..=0xFFFF, Slot::Bus);
+
+        let mut mmu = vec![0xC000..=0xFDFF, 0xFEA0..=0xFEFF, 0xFF80..=0xFFFE];
+        let mut ppu = vec![
+            0x8000..=0x9FFF,
+            0xFE00..=0xFE9F,
+            0xFF40..=0xFF45,
+            0xFF47..=0xFF4B,
+        ];
+        if model.is_cgb() {
+            bus.map(0xFF4D..=0xFF4D, Slot::Bus);
+            bus.map(0xFF51..=0xFF55, Slot::Bus);
+            mmu.push(0xFF70..=0xFF70);
+            ppu.extend([0xFF4F..=0xFF4F, 0xFF68..=0xFF6B]);
         }
+
+        // everything else in I/O is unused, and reads 0xFF
+        bus.insert(&mmu, Box::new(Mmu::with_model(model)));
+        bus.insert(&[0xFF04..=0xFF07], Box::new(Timer::new()));
+        bus.insert(&[0xFF01..=0xFF02], Box::new(Serial::with_model(model)));
+        bus.insert(&[0xFF10..=0xFF3F], Box::new(Apu::with_model(model)));
+        bus.insert(&ppu, Box::new(Ppu::with_model(model)));
+        bus.insert(&[0xFF00..=0xFF00], Box::new(Joypad::new()));
+
+        bus
+    }
+
+    // the cartridge slot is open bus until something is inserted
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) {
+        self.insert(&[0x0000..=0x7FFF, 0xA000..=0xBFFF], Box::new(cartridge));
+    }
+
+    // the first device of type `T` on the bus, e.g. the PPU for its frames
+    pub fn device<T: MemoryMappedDevice>(&self) -> Option<&T> {
+        self.devices
+            .iter()
+            .find_map(|device| (device.as_ref() as &dyn Any).downcast_ref())
+    }
+
+    pub fn device_mut<T: MemoryMappedDevice>(&mut self) -> Option<&mut T> {
+        self.devices
+            .iter_mut()
+            .find_map(|device| (device.as_mut() as &mut dyn Any).downcast_mut())
     }
 
     pub fn read8(&self, addr: u16) -> u8 {
-        self.memory[addr as usize]
+        if self.oam_dma_blocks(addr) {
+            // OAM itself reads as 0xFF, the source bus returns whatever DMA is reading
+            return if (0xFE00..=0xFEFF).contains(&addr) {
+                0xFF
+            } else {
+                self.oam_dma_byte
+            };
+        }
+
+        self.read_bus(addr)
+    }
+
+    // read8 without the OAM DMA restrictions, also what DMA reads through
+    fn read_bus(&self, addr: u16) -> u8 {
+        match self.slot(addr) {
+            Slot::Bus => self.read_register(addr),
+            Slot::Device(i) => self.devices[i].read(addr),
+            Slot::Unmapped => 0xFF,
+        }
     }
 
     pub fn write8(&mut self, addr: u16, value: u8) {
+        if self.oam_dma_blocks(addr) {
+            return;
+        }
+
+        self.write_bus(addr, value);
+    }
+
+    fn write_bus(&mut self, addr: u16, value: u8) {
+        match self.slot(addr) {
+            Slot::Bus => self.write_register(addr, value),
+            Slot::Device(i) => self.devices[i].write(addr, value),
+            Slot::Unmapped => {}
+        }
+    }
+
+    // registers that belong to the bus itself
+    fn read_register(&self, addr: u16) -> u8 {
         match addr {
-            // serial data register (SB)
-            0xFF01 => {
-                self.serial_data = value;
+            // only the lower 5 bits of IF exist, the rest read as 1
+            0xFF0F => self.interrupt_flag | 0xE0,
+            0xFF46 => self.oam_dma.read(),
+            0xFF4D => (self.double_speed as u8) << 7 | 0x7E | self.speed_switch_armed as u8,
+            0xFF51..=0xFF55 => self.hdma.read(addr),
+            0xFFFF => self.interrupt_enable,
+            _ => unreachable!("Not a bus register: 0x{:04X}", addr),
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0xFF0F => self.interrupt_flag = value & 0x1F,
+            0xFF46 => self.oam_dma.write(value),
+            0xFF4D => self.speed_switch_armed = value & 0x01 != 0,
+            0xFF51..=0xFF55 => {
+                let in_hblank = self.read_bus(0xFF41) & 0x03 == 0;
+                self.hdma.write(addr, value, in_hblank);
             }
+            0xFFFF => self.interrupt_enable = value,
+            _ => unreachable!("Not a bus register: 0x{:04X}", addr),
+        }
+    }
 
-            // serial control register (SC)
-            0xFF02 => {
-                // bit 7 set means "start transfer"
-                if value == 0x81 {
-                    print!("{}", self.serial_data as char);
-                }
+    fn slot(&self, addr: u16) -> Slot {
+        if addr >= HIGH_START {
+            self.high_slots[(addr - HIGH_START) as usize]
+        } else {
+            self.pages[(addr >> 8) as usize]
+        }
+    }
+
+    fn map(&mut self, range: RangeInclusive<u16>, slot: Slot) {
+        for addr in range {
+            if addr >= HIGH_START {
+                self.high_slots[(addr - HIGH_START) as usize] = slot;
+            } else {
+                self.pages[(addr >> 8) as usize] = slot;
             }
+        }
+    }
 
-            _ => {
-                self.memory[addr as usize] = value;
+    // puts `device` in front of `ranges`, replacing whatever answered there
+    // before. Below 0xFE00 the table works in 256 byte pages, so ranges
+    // there have to cover whole pages
+    pub fn attach(
+        &mut self,
+        ranges: &[RangeInclusive<u16>],
+        device: Box<dyn MemoryMappedDevice>,
+    ) -> Result<(), AttachError> {
+        for range in ranges {
+            let (start, end) = (*range.start(), *range.end());
+            let whole_pages = start & 0xFF == 0 && (end >= HIGH_START || end & 0xFF == 0xFF);
+            if start < HIGH_START && !whole_pages {
+                return Err(AttachError::PartialPage { start, end });
             }
         }
+
+        self.insert(ranges, device);
+        Ok(())
+    }
+
+    fn insert(&mut self, ranges: &[RangeInclusive<u16>], device: Box<dyn MemoryMappedDevice>) {
+        self.devices.push(device);
+        for range in ranges {
+            self.map(range.clone(), Slot::Device(self.devices.len() - 1));
+        }
+        self.drop_unmapped_devices();
+    }
+    // a device whose whole range was attached over is gone from the bus,
+    // so it's dropped rather than ticked forever
+    fn drop_unmapped_devices(&mut self) {
+        let mut mapped = vec![false; self.devices.len()];
+        for slot in self.pages.iter().chain(self.high_slots.iter()) {
+            if let Slot::Device(i) = slot {
+                mapped[*i] = true;
+            }
+        }
+
+        // where each device that's kept moves to
+        let new_index: Vec<usize> = mapped
+            .iter()
+            .scan(0, |next, &kept| {
+                let index = *next;
+                *next += kept as usize;
+                Some(index)
+            })
+            .collect();
+
+        let mut kept = mapped.iter();
+        self.devices.retain(|_| *kept.next().unwrap());
+        for slot in self.pages.iter_mut().chain(self.high_slots.iter_mut()) {
+            if let Slot::Device(i) = slot {
+                *i = new_index[*i];
+            }
+        }
+    }
+
+    // advances everything that runs alongside the CPU by `cycles` T-cycles
+    // of the CPU clock. In double speed that's only half as much real time
+    pub fn tick(&mut self, cycles: u8) {
+        let real_cycles = if self.double_speed {
+            cycles / 2
+        } else {
+            cycles
+        };
+
+        // instructions always take whole M-cycles
+        for _ in 0..cycles / 4 {
+            self.step_oam_dma();
+        }
+
+        // DIV stands still during STOP and while the speed switches
+        let clock = Clock {
+            cycles,
+            real_cycles,
+            double_speed: self.double_speed,
+            div: self.read_bus(0xFF04),
+            div_held: self.stopped || self.speed_switch_cycles > 0,
+        };
+        self.speed_switch_cycles = self.speed_switch_cycles.saturating_sub(cycles as u16);
+        for device in self.devices.iter_mut() {
+            self.interrupt_flag |= device.tick(&clock);
+        }
+
+        // HBlank DMA copies a block each time the PPU goes from drawing
+        // to HBlank
+        let lcd_mode = self.read_bus(0xFF41) & 0x03;
+        if self.lcd_mode == 3 && lcd_mode == 0 {
+            self.hdma.start_hblank_block();
+        }
+        self.lcd_mode = lcd_mode;
+        for _ in 0..real_cycles {
+            self.step_hdma();
+        }
+    }
+
+    fn step_oam_dma(&mut self) {
+        let Some((source, index)) = self.oam_dma.step() else {
+            return;
+        };
+
+        // sources from 0xE000 up land on WRAM like echo RAM does
+        let source = if source >= 0xE000 {
+            source - 0x2000
+        } else {
+            source
+        };
+        self.oam_dma_byte = self.read_bus(source);
+        self.write_bus(0xFE00 + index as u16, self.oam_dma_byte);
+    }
+
+    fn step_hdma(&mut self) {
+        if let Some((source, dest)) = self.hdma.step() {
+            let value = self.read_bus(source);
+            self.write_bus(0x8000 + dest, value);
+        }
+    }
+
+    // true while HDMA or a speed switch has the CPU stopped
+    pub fn cpu_stalled(&self) -> bool {
+        self.hdma.stalls_cpu() || self.speed_switch_cycles > 0
+    }
+
+    // STOP resets DIV, and holds it until `resume` unless KEY1 has a
+    // speed switch armed. Returns true if the speed was switched
+    pub fn stop(&mut self) -> bool {
+        self.write_bus(0xFF04, 0);
+
+        if self.switch_speed() {
+            return true;
+        }
+
+        self.stopped = true;
+        false
+    }
+
+    pub fn resume(&mut self) {
+        self.stopped = false;
+    }
+
+    // STOP with KEY1 bit 0 set switches speed instead of stopping,
+    // returns false if no switch was armed
+    fn switch_speed(&mut self) -> bool {
+        if !self.speed_switch_armed {
+            return false;
+        }
+
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+        self.speed_switch_cycles = SPEED_SWITCH_CYCLES;
+        true
+    }
+
+    // while OAM DMA runs the CPU can't reach OAM or anything on the same
+    // bus as the source, which is either VRAM or the external bus
+    fn oam_dma_blocks(&self, addr: u16) -> bool {
+        if !self.oam_dma.active() {
+            return false;
+        }
+
+        let vram = |addr: u16| (0x8000..=0x9FFF).contains(&addr);
+        match addr {
+            0xFE00..=0xFEFF => true,
+            0xFF00..=0xFFFF => false,
+            _ => vram(addr) == vram(self.oam_dma.source()),
+        }
+    }
+
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag |= interrupt.mask();
+    }
+
+    // interrupts that are both requested and enabled, regardless of IME
+    pub fn pending_interrupts(&self) -> u8 {
+        self.interrupt_enable & self.interrupt_flag & 0x1F
+    }
+}
+
+impl MemoryInterface for Bus {
+    fn read8(&mut self, addr: u16) -> u8 {
+        Bus::read8(self, addr)
+    }
+
+    fn write8(&mut self, addr: u16, value: u8) {
+        Bus::write8(self, addr, value);
+    }
+
+    // 4 T-cycles of the CPU clock, so 2 of real time in double speed
+    fn tick_mcycle(&mut self) {
+        Bus::tick(self, 4);
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        Bus::pending_interrupts(self)
+    }
+
+    fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag &= !interrupt.mask();
+    }
+
+    fn cpu_stalled(&self) -> bool {
+        Bus::cpu_stalled(self)
+    }
+
+    fn stop(&mut self) -> bool {
+        Bus::stop(self)
+    }
+
+    fn resume(&mut self) {
+        Bus::resume(self);
+    }
+
+    fn joypad_lines(&self) -> u8 {
+        self.read_bus(0xFF00) & 0x0F
+    }
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::joypad::Button;
+
+    // a single register that counts how often it's ticked
+    struct Register {
+        value: u8,
+        ticks: Rc<Cell<u32>>,
+    }
+
+    impl Register {
+        fn new(value: u8) -> (Box<Self>, Rc<Cell<u32>>) {
+            let ticks = Rc::new(Cell::new(0));
+            let register = Register {
+                value,
+                ticks: ticks.clone(),
+            };
+
+            (Box::new(register), ticks)
+        }
+    }
+
+    impl MemoryMappedDevice for Register {
+        fn read(&self, _addr: u16) -> u8 {
+            self.value
+        }
+
+        fn write(&mut self, _addr: u16, value: u8) {
+            self.value = value;
+        }
+
+        fn tick(&mut self, _clock: &Clock) -> u8 {
+            self.ticks.set(self.ticks.get() + 1);
+            0
+        }
+    }
+
+    fn run(cpu: &mut Cpu, bus: &mut Bus, steps: usize) {
+        for _ in 0..steps {
+            cpu.step(bus);
+        }
+    }
+
+    #[test]
+    fn the_cpu_ticks_the_bus_every_m_cycle() {
+        let mut bus = Bus::new();
+        let mut cpu = Cpu::new();
+        cpu.regs.pc = 0xC000;
+
+        // WRAM is zeroed, so 64 NOPs take DIV's first 256 cycles
+        run(&mut cpu, &mut bus, 63);
+        assert_eq!(bus.read8(0xFF04), 0x00);
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!(bus.read8(0xFF04), 0x01);
+    }
+
+    #[test]
+    fn stop_resets_and_holds_div_until_a_button_is_pressed() {
+        let mut bus = Bus::new();
+        let mut cpu = Cpu::new();
+
+        // STOP from WRAM with the action buttons selected
+        bus.write8(0xC000, 0x10);
+        bus.write8(0xC001, 0x00);
+        bus.write8(0xFF00, 0x10);
+        cpu.regs.pc = 0xC000;
+
+        for _ in 0..128 {
+            bus.tick(4);
+        }
+        assert_eq!(bus.read8(0xFF04), 0x02);
+
+        run(&mut cpu, &mut bus, 1000);
+        assert!(cpu.stopped);
+        assert_eq!(bus.read8(0xFF04), 0x00);
+        let timer = bus.device::<Timer>().unwrap();
+        assert_eq!(timer.counter(), 0);
+
+        let joypad = bus.device_mut::<Joypad>().unwrap();
+        joypad.set_button(Button::A, true);
+        run(&mut cpu, &mut bus, 100);
+        assert!(!cpu.stopped);
+        assert_ne!(bus.device::<Timer>().unwrap().counter(), 0);
+    }
+
+    #[test]
+    fn attached_devices_answer_their_range_and_get_ticked() {
+        let mut bus = Bus::new();
+        let (register, ticks) = Register::new(0x12);
+        bus.attach(&[0xFF60..=0xFF61], register).unwrap();
+
+        assert_eq!(bus.read8(0xFF61), 0x12);
+        bus.write8(0xFF60, 0x34);
+        assert_eq!(bus.read8(0xFF61), 0x34);
+        assert_eq!(bus.read8(0xFF62), 0xFF);
+
+        bus.tick(4);
+        assert_eq!(ticks.get(), 1);
+    }
+
+    #[test]
+    fn devices_attached_over_are_dropped() {
+        let mut bus = Bus::new();
+        let (first, first_ticks) = Register::new(0x01);
+        let (second, second_ticks) = Register::new(0x02);
+        let (third, third_ticks) = Register::new(0x03);
+        bus.attach(&[0xFF60..=0xFF60], first).unwrap();
+        bus.attach(&[0xFF62..=0xFF62], second).unwrap();
+        bus.attach(&[0xFF60..=0xFF60], third).unwrap();
+
+        assert_eq!(bus.read8(0xFF60), 0x03);
+        assert_eq!(bus.read8(0xFF62), 0x02);
+
+        bus.tick(4);
+        assert_eq!(first_ticks.get(), 0);
+        assert_eq!(second_ticks.get(), 1);
+        assert_eq!(third_ticks.get(), 1);
+    }
+
+    #[test]
+    fn partly_covered_devices_keep_the_rest_of_their_range() {
+        let mut bus = Bus::new();
+        let (first, first_ticks) = Register::new(0x01);
+        let (second, _) = Register::new(0x02);
+        bus.attach(&[0xFF60..=0xFF61], first).unwrap();
+        bus.attach(&[0xFF61..=0xFF61], second).unwrap();
+
+        assert_eq!(bus.read8(0xFF60), 0x01);
+        assert_eq!(bus.read8(0xFF61), 0x02);
+
+        bus.tick(4);
+        assert_eq!(first_ticks.get(), 1);
+    }
+
+    #[test]
+    fn attach_rejects_ranges_that_split_a_page() {
+        let mut bus = Bus::new();
+        let (register, _) = Register::new(0x12);
+
+        let result = bus.attach(&[0xC000..=0xC0FF, 0xD000..=0xD07F], register);
+        assert_eq!(
+            result,
+            Err(AttachError::PartialPage {
+                start: 0xD000,
+                end: 0xD07F
+            })
+        );
+
+        // nothing gets mapped
+        bus.write8(0xC000, 0x34);
+        assert_eq!(bus.read8(0xC000), 0x34);
+    }
+
+    #[test]
+    fn built_in_devices_are_replaced_like_any_other() {
+        let mut bus = Bus::new();
+        assert!(bus.device::<Timer>().is_some());
+
+        let (register, ticks) = Register::new(0x12);
+        bus.attach(&[0xFF04..=0xFF07], register).unwrap();
+        assert!(bus.device::<Timer>().is_none());
+        assert_eq!(bus.read8(0xFF04), 0x12);
+
+        bus.tick(4);
+        assert_eq!(ticks.get(), 1);
+    }
+
+    #[test]
+    fn hblank_dma_copies_a_block_when_the_ppu_enters_hblank() {
+        let mut bus = Bus::with_model(Model::Cgb);
+        bus.write8(0xC000, 0xAB);
+        for (addr, value) in [
+            (0xFF51, 0xC0),
+            (0xFF52, 0x00),
+            (0xFF53, 0x80),
+            (0xFF54, 0x00),
+        ] {
+            bus.write8(addr, value);
+        }
+        bus.write8(0xFF40, 0x91);
+        bus.write8(0xFF55, 0x80);
+
+        while bus.read8(0xFF41) & 0x03 != 3 {
+            bus.tick(4);
+        }
+        assert_eq!(bus.read8(0xFF55), 0x00);
+        while bus.read8(0xFF41) & 0x03 != 0 {
+            bus.tick(4);
+        }
+        while bus.cpu_stalled() {
+            bus.tick(4);
+        }
+        assert_eq!(bus.read8(0xFF55), 0xFF);
+        assert_eq!(bus.read8(0x8000), 0xAB);
     }
 }
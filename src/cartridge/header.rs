@@ -0,0 +1,148 @@
+use std::fmt;
+
+use super::CartridgeError;
+
+// the header lives at 0x0100-0x014F of every ROM
+pub const HEADER_END: usize = 0x0150;
+
+const TITLE_START: usize = 0x0134;
+const TITLE_END: usize = 0x0143; // inclusive, overlaps the CGB flag
+const CGB_FLAG: usize = 0x0143;
+const NEW_LICENSEE_CODE: usize = 0x0144;
+const SGB_FLAG: usize = 0x0146;
+const CARTRIDGE_TYPE: usize = 0x0147;
+const ROM_SIZE: usize = 0x0148;
+const RAM_SIZE: usize = 0x0149;
+const OLD_LICENSEE_CODE: usize = 0x014B;
+const VERSION: usize = 0x014C;
+const HEADER_CHECKSUM: usize = 0x014D;
+const GLOBAL_CHECKSUM: usize = 0x014E;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CgbSupport {
+    None,     // DMG only
+    Enhanced, // 0x80 - works on both
+    Only,     // 0xC0 - CGB only
+}
+
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub title: String,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub cartridge_type: u8,
+    pub rom_size: usize, // in bytes
+    pub ram_size: usize, // in bytes
+    pub licensee: String,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+impl Header {
+    pub fn parse(rom: &[u8]) -> Result<Self, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::TooSmall(rom.len()));
+        }
+
+        let cgb = match rom[CGB_FLAG] {
+            0x80 => CgbSupport::Enhanced,
+            0xC0 => CgbSupport::Only,
+            _ => CgbSupport::None,
+        };
+
+        // CGB carts reuse the last title byte for the CGB flag (and newer
+        // ones the last 4 for a manufacturer code), but the title is always
+        // padded with zeros so stopping at the first one works for both
+        let title_end = if cgb == CgbSupport::None {
+            TITLE_END
+        } else {
+            CGB_FLAG - 1
+        };
+        let title = rom[TITLE_START..=title_end]
+            .iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| b as char)
+            .collect();
+
+        // 0x33 in the old field means "look at the new two character code"
+        let licensee = if rom[OLD_LICENSEE_CODE] == 0x33 {
+            rom[NEW_LICENSEE_CODE..NEW_LICENSEE_CODE + 2]
+                .iter()
+                .map(|&b| b as char)
+                .collect()
+        } else {
+            format!("{:02X}", rom[OLD_LICENSEE_CODE])
+        };
+
+        let rom_size = match rom[ROM_SIZE] {
+            // 32 KiB << n, i.e. 2 << n banks of 16 KiB
+            code @ 0x00..=0x08 => 0x8000 << code,
+            code => return Err(CartridgeError::InvalidRomSize(code)),
+        };
+
+        let ram_size = match rom[RAM_SIZE] {
+            0x00 => 0,
+            0x01 => 0x800, // unofficial 2 KiB
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            code => return Err(CartridgeError::InvalidRamSize(code)),
+        };
+
+        Ok(Self {
+            title,
+            cgb,
+            sgb: rom[SGB_FLAG] == 0x03,
+            cartridge_type: rom[CARTRIDGE_TYPE],
+            rom_size,
+            ram_size,
+            licensee,
+            version: rom[VERSION],
+            header_checksum: rom[HEADER_CHECKSUM],
+            global_checksum: (rom[GLOBAL_CHECKSUM] as u16) << 8 | rom[GLOBAL_CHECKSUM + 1] as u16,
+        })
+    }
+
+    // cartridge types with a battery keeping the external RAM (and RTC) alive
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self.cartridge_type,
+            0x03 | 0x06 | 0x09 | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E
+        )
+    }
+
+    // the boot ROM refuses to start a cartridge when this doesn't match
+    pub fn header_checksum_valid(&self, rom: &[u8]) -> bool {
+        let checksum = rom[TITLE_START..HEADER_CHECKSUM]
+            .iter()
+            .fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1));
+
+        checksum == self.header_checksum
+    }
+
+    // sum of every byte except the checksum itself, never checked by hardware
+    pub fn global_checksum_valid(&self, rom: &[u8]) -> bool {
+        let checksum = rom
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != GLOBAL_CHECKSUM && *i != GLOBAL_CHECKSUM + 1)
+            .fold(0u16, |sum, (_, &b)| sum.wrapping_add(b as u16));
+
+        checksum == self.global_checksum
+    }
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "title:     {}", self.title)?;
+        writeln!(f, "type:      0x{:02X}", self.cartridge_type)?;
+        writeln!(f, "rom size:  {} KiB", self.rom_size / 1024)?;
+        writeln!(f, "ram size:  {} KiB", self.ram_size / 1024)?;
+        writeln!(f, "cgb:       {:?}", self.cgb)?;
+        writeln!(f, "sgb:       {}", self.sgb)?;
+        writeln!(f, "licensee:  {}", self.licensee)?;
+        write!(f, "version:   {}", self.version)
+    }
+}
@@ -0,0 +1,218 @@
+use super::{Mbc, ram_offset, rom_byte};
+
+const LOGO_START: usize = 0x0104;
+const LOGO_END: usize = 0x0134;
+
+pub struct Mbc1 {
+    ram_enabled: bool,
+    bank1: u8,  // 5-bit ROM bank register (0x2000-0x3FFF)
+    bank2: u8,  // 2-bit upper ROM / RAM bank register (0x4000-0x5FFF)
+    mode: bool, // banking mode select (0x6000-0x7FFF)
+
+    // MBC1M multicarts leave bit 4 of bank1 unconnected, so bank2 lands
+    // on bits 4-5 of the ROM bank instead of 5-6
+    multicart: bool,
+}
+
+impl Mbc1 {
+    pub fn new(rom: &[u8]) -> Self {
+        Self {
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            mode: false,
+            multicart: is_multicart(rom),
+        }
+    }
+
+    fn bank2_shift(&self) -> u8 {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    fn rom_bank_low(&self) -> usize {
+        // 0x0000-0x3FFF only sees bank2 in mode 1
+        if self.mode {
+            (self.bank2 << self.bank2_shift()) as usize
+        } else {
+            0
+        }
+    }
+
+    fn rom_bank_high(&self) -> usize {
+        let bank1 = if self.multicart {
+            self.bank1 & 0x0F
+        } else {
+            self.bank1
+        };
+
+        ((self.bank2 << self.bank2_shift()) | bank1) as usize
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.mode { self.bank2 as usize } else { 0 }
+    }
+}
+
+impl Mbc for Mbc1 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => rom_byte(rom, self.rom_bank_low(), addr),
+            _ => rom_byte(rom, self.rom_bank_high(), addr),
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            // any value with 0xA in the lower nibble enables RAM
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+
+            // writing 0 selects bank 1, the check is on all 5 bits so
+            // banks 0x20/0x40/0x60 can't be reached in the upper region
+            0x2000..=0x3FFF => {
+                self.bank1 = value & 0x1F;
+                if self.bank1 == 0 {
+                    self.bank1 = 1;
+                }
+            }
+
+            0x4000..=0x5FFF => self.bank2 = value & 0x03,
+
+            _ => self.mode = value & 0x01 != 0,
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+
+        ram_offset(ram, self.ram_bank(), addr).map_or(0xFF, |i| ram[i])
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+
+        if let Some(i) = ram_offset(ram, self.ram_bank(), addr) {
+            ram[i] = value;
+        }
+    }
+}
+
+// there's no header flag for MBC1M, but every game on a multicart has its
+// own header so the boot logo shows up again at the start of banks
+// 0x10, 0x20 and 0x30 of a 1 MiB ROM
+fn is_multicart(rom: &[u8]) -> bool {
+    if rom.len() != 0x100000 {
+        return false;
+    }
+
+    let logo = &rom[LOGO_START..LOGO_END];
+    let copies = (1..4)
+        .map(|game| game * 0x40000)
+        .filter(|base| &rom[base + LOGO_START..base + LOGO_END] == logo)
+        .count();
+
+    copies > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{RAM_BANK_SIZE, ROM_BANK_SIZE, mapped_bank, numbered_rom};
+
+    #[test]
+    fn bank_0_in_the_upper_region_selects_bank_1() {
+        let rom = numbered_rom(128);
+        let mut mbc = Mbc1::new(&rom);
+
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mapped_bank(&mbc, &rom, 0x4000), 1);
+
+        // only the 5 bits of bank1 are checked, so 0x20 turns into 0x21
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mapped_bank(&mbc, &rom, 0x4000), 0x21);
+
+        mbc.write_rom(0x2000, 0x1F);
+        assert_eq!(mapped_bank(&mbc, &rom, 0x4000), 0x3F);
+    }
+
+    #[test]
+    fn mode_1_maps_bank2_into_the_lower_region() {
+        let rom = numbered_rom(128);
+        let mut mbc = Mbc1::new(&rom);
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(mapped_bank(&mbc, &rom, 0x0000), 0);
+
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mapped_bank(&mbc, &rom, 0x0000), 0x40);
+        assert_eq!(mapped_bank(&mbc, &rom, 0x4000), 0x41);
+    }
+
+    #[test]
+    fn bank_numbers_wrap_on_small_roms() {
+        let rom = numbered_rom(4);
+        let mut mbc = Mbc1::new(&rom);
+
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(mapped_bank(&mbc, &rom, 0x4000), 1);
+    }
+
+    #[test]
+    fn ram_is_banked_only_in_mode_1() {
+        let rom = numbered_rom(4);
+        let mut ram = vec![0; 4 * RAM_BANK_SIZE];
+        let mut mbc = Mbc1::new(&rom);
+
+        // disabled until 0xA is written to 0x0000-0x1FFF
+        mbc.write_ram(&mut ram, 0xA000, 0x11);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFF);
+
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x02);
+        mbc.write_ram(&mut ram, 0xA000, 0x11);
+        assert_eq!(ram[0], 0x11);
+
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_ram(&mut ram, 0xA000, 0x22);
+        assert_eq!(ram[2 * RAM_BANK_SIZE], 0x22);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x22);
+
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFF);
+    }
+
+    #[test]
+    fn multicarts_shift_bank2_by_4() {
+        let mut rom = numbered_rom(64);
+        for game in 0..4 {
+            let base = game * 0x10 * ROM_BANK_SIZE;
+            for (i, byte) in rom[base + LOGO_START..base + LOGO_END]
+                .iter_mut()
+                .enumerate()
+            {
+                *byte = i as u8 | 0x80;
+            }
+        }
+        let mut mbc = Mbc1::new(&rom);
+        assert!(mbc.multicart);
+
+        // bit 4 of bank1 isn't connected
+        mbc.write_rom(0x2000, 0x12);
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mapped_bank(&mbc, &rom, 0x4000), 0x12);
+
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_rom(0x4000, 0x03);
+        assert_eq!(mapped_bank(&mbc, &rom, 0x0000), 0x30);
+    }
+
+    #[test]
+    fn plain_1mib_roms_are_not_multicarts() {
+        let mut rom = numbered_rom(64);
+        rom[LOGO_START] = 0xCE;
+
+        assert!(!Mbc1::new(&rom).multicart);
+    }
+}
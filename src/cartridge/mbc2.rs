@@ -0,0 +1,110 @@
+use super::{Mbc, rom_byte};
+
+// 512 half-bytes of RAM built into the MBC itself
+pub const MBC2_RAM_SIZE: usize = 0x200;
+
+pub struct Mbc2 {
+    ram_enabled: bool,
+    rom_bank: u8, // 4-bit
+}
+
+impl Mbc2 {
+    pub fn new() -> Self {
+        Self {
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl Default for Mbc2 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => rom_byte(rom, 0, addr),
+            _ => rom_byte(rom, self.rom_bank as usize, addr),
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        // both registers live in 0x0000-0x3FFF, address bit 8 picks which
+        if addr > 0x3FFF {
+            return;
+        }
+
+        if addr & 0x0100 == 0 {
+            self.ram_enabled = value & 0x0F == 0x0A;
+        } else {
+            self.rom_bank = value & 0x0F;
+            if self.rom_bank == 0 {
+                self.rom_bank = 1;
+            }
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+
+        // only 9 address lines are decoded, so the 512 entries repeat
+        // across the whole 0xA000-0xBFFF range. The upper nibble isn't
+        // connected and reads back as 1s
+        ram[addr as usize & (MBC2_RAM_SIZE - 1)] | 0xF0
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+
+        ram[addr as usize & (MBC2_RAM_SIZE - 1)] = value & 0x0F;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{mapped_bank, numbered_rom};
+
+    #[test]
+    fn address_bit_8_selects_the_register() {
+        let rom = numbered_rom(16);
+        let mut mbc = Mbc2::new();
+
+        // bit 8 clear is RAM enable, even with a bank number
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(mapped_bank(&mbc, &rom, 0x4000), 1);
+
+        mbc.write_rom(0x2100, 0x05);
+        assert_eq!(mapped_bank(&mbc, &rom, 0x4000), 5);
+
+        mbc.write_rom(0x0100, 0x00);
+        assert_eq!(mapped_bank(&mbc, &rom, 0x4000), 1);
+
+        // writes above 0x3FFF do nothing
+        mbc.write_rom(0x4100, 0x03);
+        assert_eq!(mapped_bank(&mbc, &rom, 0x4000), 1);
+    }
+
+    #[test]
+    fn ram_is_4_bits_wide_and_repeats() {
+        let mut ram = vec![0; MBC2_RAM_SIZE];
+        let mut mbc = Mbc2::new();
+
+        mbc.write_ram(&mut ram, 0xA000, 0x05);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFF);
+
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(&mut ram, 0xA001, 0xAB);
+        assert_eq!(ram[1], 0x0B);
+        assert_eq!(mbc.read_ram(&ram, 0xA001), 0xFB);
+        assert_eq!(mbc.read_ram(&ram, 0xA201), 0xFB);
+        assert_eq!(mbc.read_ram(&ram, 0xBE01), 0xFB);
+    }
+}
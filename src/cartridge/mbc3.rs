@@ -0,0 +1,514 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{Mbc, ram_offset, rom_byte};
+
+// VBA/BGB append the clock to the save: live and latched registers as
+// 5 little endian u32s each, then the unix time of the save as a u64.
+// Older versions of VBA wrote a 32-bit timestamp instead
+pub const RTC_FOOTER_SIZE: usize = 48;
+const RTC_FOOTER_SIZE_OLD: usize = 44;
+
+pub struct Mbc3 {
+    ram_enabled: bool, // also gates the RTC registers
+    rom_bank: u8,      // 7-bit
+    ram_select: u8,    // 0x00-0x03 RAM bank, 0x08-0x0C RTC register
+    latch_armed: bool, // 0x00 was written to 0x6000-0x7FFF
+    pub rtc: Option<Rtc>,
+}
+
+impl Mbc3 {
+    pub fn new(has_rtc: bool) -> Self {
+        Self {
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_select: 0,
+            latch_armed: false,
+            rtc: has_rtc.then(Rtc::new),
+        }
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => rom_byte(rom, 0, addr),
+            _ => rom_byte(rom, self.rom_bank as usize, addr),
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+
+            // unlike MBC1 the check is on all 7 bits, so only 0 maps to 1
+            0x2000..=0x3FFF => {
+                self.rom_bank = value & 0x7F;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+
+            0x4000..=0x5FFF => self.ram_select = value,
+
+            // writing 0x00 then 0x01 copies the live clock into the latch
+            _ => {
+                if self.latch_armed
+                    && value == 0x01
+                    && let Some(rtc) = self.rtc.as_mut()
+                {
+                    rtc.latch();
+                }
+                self.latch_armed = value == 0x00;
+            }
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+
+        match (self.ram_select, self.rtc.as_ref()) {
+            (0x00..=0x03, _) => {
+                ram_offset(ram, self.ram_select as usize, addr).map_or(0xFF, |i| ram[i])
+            }
+            (0x08..=0x0C, Some(rtc)) => rtc.read(self.ram_select),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+
+        match (self.ram_select, self.rtc.as_mut()) {
+            (0x00..=0x03, _) => {
+                if let Some(i) = ram_offset(ram, self.ram_select as usize, addr) {
+                    ram[i] = value;
+                }
+            }
+            (0x08..=0x0C, Some(rtc)) => rtc.write(self.ram_select, value),
+            _ => {}
+        }
+    }
+
+    fn rtc(&self) -> Option<&Rtc> {
+        self.rtc.as_ref()
+    }
+
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        self.rtc.as_mut()
+    }
+}
+
+// register layout shared by the live and latched clock
+#[derive(Debug, Default, Copy, Clone)]
+pub struct RtcRegisters {
+    pub seconds: u8,     // 0x08, 6 bits
+    pub minutes: u8,     // 0x09, 6 bits
+    pub hours: u8,       // 0x0A, 5 bits
+    pub days: u16,       // 0x0B + bit 0 of 0x0C, 9 bits
+    pub halted: bool,    // bit 6 of 0x0C
+    pub day_carry: bool, // bit 7 of 0x0C, sticky until written
+}
+
+impl RtcRegisters {
+    // values of registers 0x08-0x0C in order
+    fn to_bytes(self) -> [u8; 5] {
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            (self.days & 0xFF) as u8,
+            (self.days >> 8) as u8 & 0x01
+                | if self.halted { 0x40 } else { 0 }
+                | if self.day_carry { 0x80 } else { 0 },
+        ]
+    }
+
+    fn from_bytes(bytes: [u8; 5]) -> Self {
+        Self {
+            seconds: bytes[0] & 0x3F,
+            minutes: bytes[1] & 0x3F,
+            hours: bytes[2] & 0x1F,
+            days: (bytes[4] as u16 & 0x01) << 8 | bytes[3] as u16,
+            halted: bytes[4] & 0x40 != 0,
+            day_carry: bytes[4] & 0x80 != 0,
+        }
+    }
+
+    fn in_range(&self) -> bool {
+        self.seconds < 60 && self.minutes < 60 && self.hours < 24
+    }
+
+    // one tick of the 1 Hz counter. Each counter is only as wide as its
+    // register, so a value written out of range (e.g. 61 seconds) counts up
+    // to the register limit and wraps to 0 without carrying
+    fn tick(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+
+        self.days += 1;
+        if self.days == 512 {
+            self.days = 0;
+            self.day_carry = true;
+        }
+    }
+
+    fn advance(&mut self, mut seconds: u64) {
+        // step out-of-range values by hand until they wrap, then the rest
+        // can be done in one go
+        while seconds > 0 && !self.in_range() {
+            self.tick();
+            seconds -= 1;
+        }
+
+        let total = self.days as u64 * 86400
+            + self.hours as u64 * 3600
+            + self.minutes as u64 * 60
+            + self.seconds as u64
+            + seconds;
+
+        let days = total / 86400;
+        if days >= 512 {
+            self.day_carry = true;
+        }
+
+        self.days = (days % 512) as u16;
+        self.hours = (total % 86400 / 3600) as u8;
+        self.minutes = (total % 3600 / 60) as u8;
+        self.seconds = (total % 60) as u8;
+    }
+}
+
+// unix time in milliseconds, the host clock unless something else is
+// plugged in
+pub type TimeSource = Box<dyn Fn() -> u64>;
+
+// the MBC3 real time clock. The crystal on the cartridge is emulated with
+// the host clock, so it keeps counting while the emulator isn't running as
+// long as the state (including `last_update`) is saved with the game
+pub struct Rtc {
+    pub live: RtcRegisters,
+    pub latched: RtcRegisters,
+    pub last_update: u64, // unix time in milliseconds
+    subsecond_ms: u64,    // progress towards the next tick
+    writes: u64,          // clock registers set by the game so far
+    now: TimeSource,
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Self::with_time_source(Box::new(unix_millis))
+    }
+
+    pub fn with_time_source(now: TimeSource) -> Self {
+        Self {
+            live: RtcRegisters::default(),
+            latched: RtcRegisters::default(),
+            last_update: now(),
+            subsecond_ms: 0,
+            writes: 0,
+            now,
+        }
+    }
+
+    // the clock carries on from the new source's current time
+    pub fn set_time_source(&mut self, now: TimeSource) {
+        self.update();
+        self.last_update = now();
+        self.now = now;
+    }
+
+    // catch the live registers up with the time source
+    pub fn update(&mut self) {
+        let now = (self.now)();
+        // the host clock can go backwards, don't rewind the game's clock
+        let elapsed = now.saturating_sub(self.last_update);
+        self.last_update = now;
+
+        if self.live.halted {
+            return;
+        }
+
+        let elapsed = elapsed + self.subsecond_ms;
+        self.subsecond_ms = elapsed % 1000;
+        self.live.advance(elapsed / 1000);
+    }
+
+    fn latch(&mut self) {
+        self.update();
+        self.latched = self.live;
+    }
+
+    // only changes when the game sets the clock, not as it runs
+    pub fn writes(&self) -> u64 {
+        self.writes
+    }
+
+    fn read(&self, reg: u8) -> u8 {
+        self.latched.to_bytes()[(reg - 0x08) as usize]
+    }
+
+    pub fn save_footer(&mut self) -> [u8; RTC_FOOTER_SIZE] {
+        self.update();
+
+        let mut footer = [0; RTC_FOOTER_SIZE];
+        let registers = self
+            .live
+            .to_bytes()
+            .into_iter()
+            .chain(self.latched.to_bytes());
+        for (chunk, value) in footer.chunks_exact_mut(4).zip(registers) {
+            chunk.copy_from_slice(&(value as u32).to_le_bytes());
+        }
+        footer[40..].copy_from_slice(&(self.last_update / 1000).to_le_bytes());
+
+        footer
+    }
+
+    // a missing or malformed footer leaves the clock as it is
+    pub fn load_footer(&mut self, footer: &[u8]) {
+        let timestamp = match footer.len() {
+            RTC_FOOTER_SIZE => u64::from_le_bytes(footer[40..48].try_into().unwrap()),
+            RTC_FOOTER_SIZE_OLD => u32::from_le_bytes(footer[40..44].try_into().unwrap()) as u64,
+            _ => return,
+        };
+
+        let register = |i: usize| footer[i * 4];
+        self.live = RtcRegisters::from_bytes(std::array::from_fn(register));
+        self.latched = RtcRegisters::from_bytes(std::array::from_fn(|i| register(i + 5)));
+
+        // the clock kept running while the emulator was closed
+        self.last_update = timestamp * 1000;
+        self.subsecond_ms = 0;
+        self.update();
+    }
+
+    fn write(&mut self, reg: u8, value: u8) {
+        // bring the clock up to date so the time before the write is kept
+        self.update();
+        self.writes += 1;
+
+        let r = &mut self.live;
+        match reg {
+            0x08 => {
+                r.seconds = value & 0x3F;
+                // writing seconds also resets the sub-second divider
+                self.subsecond_ms = 0;
+            }
+            0x09 => r.minutes = value & 0x3F,
+            0x0A => r.hours = value & 0x1F,
+            0x0B => r.days = (r.days & 0x100) | value as u16,
+            _ => {
+                r.days = (r.days & 0xFF) | ((value as u16 & 0x01) << 8);
+                r.halted = value & 0x40 != 0;
+                r.day_carry = value & 0x80 != 0;
+            }
+        }
+    }
+}
+
+impl Default for Rtc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::cartridge::RAM_BANK_SIZE;
+
+    // an RTC cartridge whose clock only moves when the test says so
+    fn mbc3() -> (Mbc3, Rc<Cell<u64>>) {
+        let time = Rc::new(Cell::new(1_700_000_000_000));
+        let now = time.clone();
+
+        let mut mbc = Mbc3::new(false);
+        mbc.rtc = Some(Rtc::with_time_source(Box::new(move || now.get())));
+        mbc.write_rom(0x0000, 0x0A);
+
+        (mbc, time)
+    }
+
+    fn pass(time: &Cell<u64>, seconds: u64) {
+        time.set(time.get() + seconds * 1000);
+    }
+
+    fn latch(mbc: &mut Mbc3) {
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+    }
+
+    // registers 0x08-0x0C through the RAM window
+    fn read_clock(mbc: &mut Mbc3) -> [u8; 5] {
+        std::array::from_fn(|i| {
+            mbc.write_rom(0x4000, 0x08 + i as u8);
+            mbc.read_ram(&[], 0xA000)
+        })
+    }
+
+    fn set_clock(mbc: &mut Mbc3, registers: [u8; 5]) {
+        for (i, value) in registers.into_iter().enumerate() {
+            mbc.write_rom(0x4000, 0x08 + i as u8);
+            mbc.write_ram(&mut [], 0xA000, value);
+        }
+    }
+
+    #[test]
+    fn seconds_minutes_and_hours_roll_over() {
+        let (mut mbc, time) = mbc3();
+        set_clock(&mut mbc, [59, 59, 23, 0x00, 0x00]);
+
+        pass(&time, 1);
+        latch(&mut mbc);
+        assert_eq!(read_clock(&mut mbc), [0, 0, 0, 0x01, 0x00]);
+
+        pass(&time, 3661);
+        latch(&mut mbc);
+        assert_eq!(read_clock(&mut mbc), [1, 1, 1, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn day_counter_carries_into_dh() {
+        let (mut mbc, time) = mbc3();
+        set_clock(&mut mbc, [59, 59, 23, 0xFF, 0x00]);
+
+        pass(&time, 1);
+        latch(&mut mbc);
+        assert_eq!(read_clock(&mut mbc), [0, 0, 0, 0x00, 0x01]);
+
+        // past day 511 it wraps and sets the sticky carry bit
+        set_clock(&mut mbc, [59, 59, 23, 0xFF, 0x01]);
+        pass(&time, 1);
+        latch(&mut mbc);
+        assert_eq!(read_clock(&mut mbc), [0, 0, 0, 0x00, 0x80]);
+
+        pass(&time, 86400);
+        latch(&mut mbc);
+        assert_eq!(read_clock(&mut mbc), [0, 0, 0, 0x01, 0x80]);
+    }
+
+    #[test]
+    fn halt_bit_stops_the_clock() {
+        let (mut mbc, time) = mbc3();
+        set_clock(&mut mbc, [10, 0, 0, 0x00, 0x40]);
+
+        pass(&time, 100);
+        latch(&mut mbc);
+        assert_eq!(read_clock(&mut mbc), [10, 0, 0, 0x00, 0x40]);
+
+        mbc.write_ram(&mut [], 0xA000, 0x00);
+        pass(&time, 5);
+        latch(&mut mbc);
+        assert_eq!(read_clock(&mut mbc), [15, 0, 0, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn latch_needs_0x00_then_0x01() {
+        let (mut mbc, time) = mbc3();
+
+        pass(&time, 5);
+        assert_eq!(read_clock(&mut mbc)[0], 0);
+
+        // 0x01 on its own doesn't latch
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(read_clock(&mut mbc)[0], 0);
+
+        // neither does a sequence broken up by something else
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x02);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(read_clock(&mut mbc)[0], 0);
+
+        latch(&mut mbc);
+        assert_eq!(read_clock(&mut mbc)[0], 5);
+
+        // the latched copy holds still while the clock keeps going
+        pass(&time, 5);
+        assert_eq!(read_clock(&mut mbc)[0], 5);
+        latch(&mut mbc);
+        assert_eq!(read_clock(&mut mbc)[0], 10);
+    }
+
+    #[test]
+    fn ram_bank_register_selects_ram_or_a_clock_register() {
+        let (mut mbc, _) = mbc3();
+        let mut ram = vec![0; 4 * RAM_BANK_SIZE];
+
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_ram(&mut ram, 0xA000, 0x12);
+
+        mbc.write_rom(0x4000, 0x08);
+        mbc.write_ram(&mut ram, 0xA000, 30);
+        latch(&mut mbc);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 30);
+        assert_eq!(ram[RAM_BANK_SIZE], 0x12);
+
+        // 0x04-0x07 and past 0x0C select nothing
+        for select in [0x04, 0x0D] {
+            mbc.write_rom(0x4000, select);
+            assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFF);
+        }
+
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x12);
+
+        // the clock is behind the same enable as RAM
+        mbc.write_rom(0x4000, 0x08);
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFF);
+    }
+
+    #[test]
+    fn footer_round_trip_counts_the_time_in_between() {
+        let (mut mbc, time) = mbc3();
+        set_clock(&mut mbc, [0, 30, 12, 0x05, 0x00]);
+        latch(&mut mbc);
+        pass(&time, 20);
+
+        let footer = mbc.rtc.as_mut().unwrap().save_footer();
+        assert_eq!(footer.len(), RTC_FOOTER_SIZE);
+        assert_eq!(&footer[0..4], &20u32.to_le_bytes());
+        assert_eq!(&footer[20..24], &0u32.to_le_bytes());
+
+        // an hour later the clock has moved on, the latch hasn't
+        pass(&time, 3600);
+        let now = time.clone();
+        let mut rtc = Rtc::with_time_source(Box::new(move || now.get()));
+        rtc.load_footer(&footer);
+        assert_eq!(rtc.live.to_bytes(), [20, 30, 13, 0x05, 0x00]);
+        assert_eq!(rtc.latched.to_bytes(), [0, 30, 12, 0x05, 0x00]);
+
+        // VBA's older footer with a 32-bit timestamp
+        let mut rtc = Rtc::with_time_source(Box::new(move || time.get()));
+        rtc.load_footer(&footer[..RTC_FOOTER_SIZE_OLD]);
+        assert_eq!(rtc.live.to_bytes(), [20, 30, 13, 0x05, 0x00]);
+    }
+}
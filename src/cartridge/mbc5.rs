@@ -0,0 +1,149 @@
+use super::{Mbc, RumbleCallback, ram_offset, rom_byte};
+
+pub struct Mbc5 {
+    ram_enabled: bool,
+    rom_bank: u16, // 9-bit, bank 0 can be mapped to 0x4000-0x7FFF
+    ram_bank: u8,  // 4-bit
+
+    // rumble carts wire bit 3 of the RAM bank register to the motor
+    has_rumble: bool,
+    motor_on: bool,
+    rumble_callback: Option<RumbleCallback>,
+}
+
+impl Mbc5 {
+    pub fn new(has_rumble: bool) -> Self {
+        Self {
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            motor_on: false,
+            rumble_callback: None,
+        }
+    }
+}
+
+impl Mbc for Mbc5 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => rom_byte(rom, 0, addr),
+            _ => rom_byte(rom, self.rom_bank as usize, addr),
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            // MBC5 checks the whole byte, not just the lower nibble
+            0x0000..=0x1FFF => self.ram_enabled = value == 0x0A,
+
+            // lower 8 bits of the ROM bank
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+
+            // bit 8 of the ROM bank
+            0x3000..=0x3FFF => {
+                self.rom_bank = (self.rom_bank & 0xFF) | ((value as u16 & 0x01) << 8)
+            }
+
+            0x4000..=0x5FFF => {
+                if self.has_rumble {
+                    self.ram_bank = value & 0x07;
+
+                    let motor_on = value & 0x08 != 0;
+                    if motor_on != self.motor_on {
+                        self.motor_on = motor_on;
+                        if let Some(callback) = self.rumble_callback.as_mut() {
+                            callback(motor_on);
+                        }
+                    }
+                } else {
+                    self.ram_bank = value & 0x0F;
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+
+        ram_offset(ram, self.ram_bank as usize, addr).map_or(0xFF, |i| ram[i])
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+
+        if let Some(i) = ram_offset(ram, self.ram_bank as usize, addr) {
+            ram[i] = value;
+        }
+    }
+
+    fn set_rumble_callback(&mut self, callback: RumbleCallback) {
+        self.rumble_callback = Some(callback);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::cartridge::{RAM_BANK_SIZE, mapped_bank, numbered_rom};
+
+    #[test]
+    fn rom_bank_has_9_bits_and_can_be_0() {
+        let rom = numbered_rom(512);
+        let mut mbc = Mbc5::new(false);
+
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mapped_bank(&mbc, &rom, 0x4000), 0);
+
+        mbc.write_rom(0x2000, 0x34);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!(mapped_bank(&mbc, &rom, 0x4000), 0x134);
+
+        // the low byte write leaves bit 8 alone
+        mbc.write_rom(0x2000, 0xFF);
+        assert_eq!(mapped_bank(&mbc, &rom, 0x4000), 0x1FF);
+        assert_eq!(mapped_bank(&mbc, &rom, 0x0000), 0);
+    }
+
+    #[test]
+    fn ram_enable_needs_exactly_0x0a() {
+        let mut ram = vec![0; RAM_BANK_SIZE];
+        let mut mbc = Mbc5::new(false);
+
+        mbc.write_rom(0x0000, 0x1A);
+        mbc.write_ram(&mut ram, 0xA000, 0x42);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFF);
+
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(&mut ram, 0xA000, 0x42);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x42);
+    }
+
+    #[test]
+    fn rumble_takes_bit_3_of_the_ram_bank() {
+        let mut ram = vec![0; 8 * RAM_BANK_SIZE];
+        let mut mbc = Mbc5::new(true);
+        let motor = Rc::new(RefCell::new(Vec::new()));
+        let log = motor.clone();
+        mbc.set_rumble_callback(Box::new(move |on| log.borrow_mut().push(on)));
+
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x0B);
+        mbc.write_ram(&mut ram, 0xA000, 0x42);
+        assert_eq!(ram[3 * RAM_BANK_SIZE], 0x42);
+
+        // only changes are reported
+        mbc.write_rom(0x4000, 0x08);
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(*motor.borrow(), [true, false]);
+    }
+}
@@ -0,0 +1,250 @@
+pub mod header;
+pub mod mbc1;
+pub mod mbc2;
+pub mod mbc3;
+pub mod mbc5;
+pub mod save;
+
+use std::fmt;
+
+use crate::bus::MemoryMappedDevice;
+use header::Header;
+use mbc1::Mbc1;
+use mbc2::{MBC2_RAM_SIZE, Mbc2};
+use mbc3::{Mbc3, Rtc};
+use mbc5::Mbc5;
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    TooSmall(usize), // ROM is too short to contain a header
+    InvalidRomSize(u8),
+    InvalidRamSize(u8),
+    UnsupportedType(u8),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::TooSmall(len) => write!(f, "ROM too small ({len} bytes)"),
+            CartridgeError::InvalidRomSize(code) => write!(f, "invalid ROM size code 0x{code:02X}"),
+            CartridgeError::InvalidRamSize(code) => write!(f, "invalid RAM size code 0x{code:02X}"),
+            CartridgeError::UnsupportedType(code) => {
+                write!(f, "unsupported cartridge type 0x{code:02X}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+// memory bank controller - decides which part of ROM/RAM is visible
+// and handles writes into the ROM area as register writes
+pub trait Mbc {
+    // 0x0000-0x7FFF
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8;
+    fn write_rom(&mut self, addr: u16, value: u8);
+
+    // 0xA000-0xBFFF
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8;
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, value: u8);
+
+    // only rumble cartridges have a motor to report
+    fn set_rumble_callback(&mut self, _callback: RumbleCallback) {}
+
+    // only MBC3 timer cartridges have a clock to save
+    fn rtc(&self) -> Option<&Rtc> {
+        None
+    }
+
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        None
+    }
+}
+
+// called with the new motor state whenever a rumble cartridge turns it on/off
+pub type RumbleCallback = Box<dyn FnMut(bool)>;
+
+// plain 32 KiB ROM, with optional unbanked RAM
+pub struct NoMbc;
+
+impl Mbc for NoMbc {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        // a dump shorter than the header claims reads as open bus
+        rom.get(addr as usize).copied().unwrap_or(0xFF)
+    }
+
+    // ROM is read only, writes are ignored
+    fn write_rom(&mut self, _addr: u16, _value: u8) {}
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        ram_offset(ram, 0, addr).map_or(0xFF, |i| ram[i])
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        if let Some(i) = ram_offset(ram, 0, addr) {
+            ram[i] = value;
+        }
+    }
+}
+
+// byte at `addr` within a 16 KiB ROM bank, bank numbers past the end wrap
+// around like they do on hardware where the upper address lines are unused
+pub fn rom_byte(rom: &[u8], bank: usize, addr: u16) -> u8 {
+    let offset = bank * ROM_BANK_SIZE + (addr as usize & (ROM_BANK_SIZE - 1));
+    rom[offset % rom.len()]
+}
+
+// same for 8 KiB RAM banks, None when the cartridge has no RAM
+pub fn ram_offset(ram: &[u8], bank: usize, addr: u16) -> Option<usize> {
+    if ram.is_empty() {
+        return None;
+    }
+
+    let offset = bank * RAM_BANK_SIZE + (addr as usize & (RAM_BANK_SIZE - 1));
+    Some(offset % ram.len())
+}
+
+// owns the ROM and the external RAM, mapped at 0x0000-0x7FFF and 0xA000-0xBFFF
+pub struct Cartridge {
+    pub header: Header,
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mbc: Box<dyn Mbc>,
+}
+
+impl Cartridge {
+    pub fn from_bytes(rom: Vec<u8>) -> Result<Self, CartridgeError> {
+        let header = Header::parse(&rom)?;
+        let mut ram_size = header.ram_size;
+
+        let mbc: Box<dyn Mbc> = match header.cartridge_type {
+            0x00 | 0x08 | 0x09 => Box::new(NoMbc),
+            0x01..=0x03 => Box::new(Mbc1::new(&rom)),
+            0x05 | 0x06 => {
+                // MBC2 headers report no RAM since it's inside the MBC
+                ram_size = MBC2_RAM_SIZE;
+                Box::new(Mbc2::new())
+            }
+            0x0F | 0x10 => Box::new(Mbc3::new(true)),
+            0x11..=0x13 => Box::new(Mbc3::new(false)),
+            0x19..=0x1B => Box::new(Mbc5::new(false)),
+            0x1C..=0x1E => Box::new(Mbc5::new(true)),
+            code => return Err(CartridgeError::UnsupportedType(code)),
+        };
+        let ram = vec![0; ram_size];
+
+        Ok(Self {
+            header,
+            rom,
+            ram,
+            mbc,
+        })
+    }
+
+    pub fn set_rumble_callback(&mut self, callback: impl FnMut(bool) + 'static) {
+        self.mbc.set_rumble_callback(Box::new(callback));
+    }
+
+    // replaces the host clock behind the RTC, if there is one, with `now`
+    // (unix time in milliseconds)
+    pub fn set_rtc_time_source(&mut self, now: impl Fn() -> u64 + 'static) {
+        if let Some(rtc) = self.mbc.rtc_mut() {
+            rtc.set_time_source(Box::new(now));
+        }
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn has_rtc(&self) -> bool {
+        self.mbc.rtc().is_some()
+    }
+
+    pub fn rtc(&self) -> Option<&Rtc> {
+        self.mbc.rtc()
+    }
+
+    // contents of a .sav file: the external RAM followed by the RTC footer
+    // for timer cartridges
+    pub fn save_data(&mut self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        if let Some(rtc) = self.mbc.rtc_mut() {
+            data.extend_from_slice(&rtc.save_footer());
+        }
+
+        data
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+
+        if let Some(rtc) = self.mbc.rtc_mut() {
+            rtc.load_footer(&data[len..]);
+        }
+    }
+
+    // 0x0000-0x7FFF
+    pub fn read_rom(&self, addr: u16) -> u8 {
+        self.mbc.read_rom(&self.rom, addr)
+    }
+
+    // 0x0000-0x7FFF - goes to the MBC registers
+    pub fn write_rom(&mut self, addr: u16, value: u8) {
+        self.mbc.write_rom(addr, value);
+    }
+
+    // 0xA000-0xBFFF
+    pub fn read_ram(&self, addr: u16) -> u8 {
+        self.mbc.read_ram(&self.ram, addr)
+    }
+
+    // 0xA000-0xBFFF
+    pub fn write_ram(&mut self, addr: u16, value: u8) {
+        self.mbc.write_ram(&mut self.ram, addr, value);
+    }
+}
+
+impl MemoryMappedDevice for Cartridge {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7FFF => self.read_rom(addr),
+            0xA000..=0xBFFF => self.read_ram(addr),
+            _ => unreachable!("Not a cartridge address: 0x{:04X}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x7FFF => self.write_rom(addr, value),
+            0xA000..=0xBFFF => self.write_ram(addr, value),
+            _ => unreachable!("Not a cartridge address: 0x{:04X}", addr),
+        }
+    }
+}
+
+// a ROM where every bank starts with its own number, so tests can tell
+// which bank is mapped where
+#[cfg(test)]
+pub fn numbered_rom(banks: usize) -> Vec<u8> {
+    let mut rom = vec![0; banks * ROM_BANK_SIZE];
+    for (bank, data) in rom.chunks_exact_mut(ROM_BANK_SIZE).enumerate() {
+        data[..2].copy_from_slice(&(bank as u16).to_le_bytes());
+    }
+
+    rom
+}
+
+// the bank number `numbered_rom` put at the start of the region at `addr`
+#[cfg(test)]
+pub fn mapped_bank(mbc: &dyn Mbc, rom: &[u8], addr: u16) -> u16 {
+    u16::from_le_bytes([mbc.read_rom(rom, addr), mbc.read_rom(rom, addr + 1)])
+}
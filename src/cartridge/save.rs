@@ -0,0 +1,196 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use super::Cartridge;
+use super::mbc3::Rtc;
+
+// keeps battery backed RAM in a .sav file next to the ROM
+pub struct BatterySave {
+    path: PathBuf,
+    autosave_interval: Duration,
+    last_autosave: Instant,
+    flushed: SaveState, // as of the last write, to skip unchanged saves
+}
+
+impl BatterySave {
+    pub fn new(rom_path: &Path, autosave_interval: Duration) -> Self {
+        Self {
+            path: rom_path.with_extension("sav"),
+            autosave_interval,
+            last_autosave: Instant::now(),
+            flushed: SaveState::default(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // a missing save file just means the game hasn't been played yet
+    pub fn load(&mut self, cartridge: &mut Cartridge) -> io::Result<()> {
+        match fs::read(&self.path) {
+            Ok(data) => cartridge.load_save_data(&data),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        self.flushed = SaveState::of(cartridge);
+        Ok(())
+    }
+
+    // call regularly, writes the save once the interval has passed
+    pub fn autosave(&mut self, cartridge: &mut Cartridge) -> io::Result<()> {
+        if self.last_autosave.elapsed() < self.autosave_interval {
+            return Ok(());
+        }
+
+        self.last_autosave = Instant::now();
+        self.flush(cartridge)
+    }
+
+    // writes the save if RAM changed or the game set the clock since the
+    // last flush
+    pub fn flush(&mut self, cartridge: &mut Cartridge) -> io::Result<()> {
+        if SaveState::of(cartridge) == self.flushed {
+            return Ok(());
+        }
+
+        self.write(cartridge)
+    }
+
+    // like `flush`, but a cartridge with a clock is always written so the
+    // footer says when the emulator was closed
+    pub fn flush_on_exit(&mut self, cartridge: &mut Cartridge) -> io::Result<()> {
+        if !cartridge.has_rtc() {
+            return self.flush(cartridge);
+        }
+
+        self.write(cartridge)
+    }
+
+    fn write(&mut self, cartridge: &mut Cartridge) -> io::Result<()> {
+        fs::write(&self.path, cartridge.save_data())?;
+        self.flushed = SaveState::of(cartridge);
+
+        Ok(())
+    }
+}
+
+// what decides whether a save needs writing. A running clock on its own
+// doesn't count, the footer's timestamp lets it catch up on load anyway
+#[derive(Debug, Default, PartialEq, Eq)]
+struct SaveState {
+    ram: Vec<u8>,
+    rtc_writes: u64,
+}
+
+impl SaveState {
+    fn of(cartridge: &Cartridge) -> Self {
+        Self {
+            ram: cartridge.ram().to_vec(),
+            rtc_writes: cartridge.rtc().map_or(0, Rtc::writes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::env;
+    use std::process;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::cartridge::mbc3::RTC_FOOTER_SIZE;
+
+    // 32 KiB ROM of the given type and RAM size code
+    fn cartridge(cartridge_type: u8, ram_size: u8) -> Cartridge {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = cartridge_type;
+        rom[0x0149] = ram_size;
+
+        Cartridge::from_bytes(rom).unwrap()
+    }
+
+    fn save(name: &str) -> BatterySave {
+        let rom = env::temp_dir().join(format!("gb-emulator-{}-{name}.gb", process::id()));
+        let save = BatterySave::new(&rom, Duration::ZERO);
+        let _ = fs::remove_file(save.path());
+
+        save
+    }
+
+    #[test]
+    fn unchanged_ram_is_not_written() {
+        let mut cartridge = cartridge(0x03, 0x02);
+        let mut save = save("ram");
+        save.load(&mut cartridge).unwrap();
+
+        save.flush(&mut cartridge).unwrap();
+        assert!(!save.path().exists());
+
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xA000, 0x42);
+        save.flush(&mut cartridge).unwrap();
+        assert_eq!(fs::read(save.path()).unwrap()[0], 0x42);
+
+        fs::remove_file(save.path()).unwrap();
+    }
+
+    // MBC3+TIMER+BATTERY with no RAM, and a clock the test moves by hand
+    fn timer_cartridge() -> (Cartridge, Rc<Cell<u64>>) {
+        let mut cartridge = cartridge(0x0F, 0x00);
+        let time = Rc::new(Cell::new(1_700_000_000_000));
+        let now = time.clone();
+        cartridge.set_rtc_time_source(move || now.get());
+
+        (cartridge, time)
+    }
+
+    #[test]
+    fn a_running_clock_alone_is_not_written() {
+        let (mut cartridge, time) = timer_cartridge();
+        let mut save = save("clock");
+        save.load(&mut cartridge).unwrap();
+
+        time.set(time.get() + 60_000);
+        save.flush(&mut cartridge).unwrap();
+        assert!(!save.path().exists());
+    }
+
+    #[test]
+    fn rtc_changes_are_written_without_ram() {
+        let (mut cartridge, time) = timer_cartridge();
+        let mut save = save("rtc");
+        save.load(&mut cartridge).unwrap();
+
+        // set the hours
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_rom(0x4000, 0x0A);
+        cartridge.write_ram(0xA000, 0x05);
+        time.set(time.get() + 1_500);
+        save.flush(&mut cartridge).unwrap();
+
+        let data = fs::read(save.path()).unwrap();
+        assert_eq!(data.len(), RTC_FOOTER_SIZE);
+        assert_eq!(data[0], 0x01);
+        assert_eq!(data[8], 0x05);
+        assert_eq!(&data[40..], &1_700_000_001u64.to_le_bytes());
+
+        fs::remove_file(save.path()).unwrap();
+    }
+
+    #[test]
+    fn rtc_cartridges_are_always_written_on_exit() {
+        let (mut cartridge, _) = timer_cartridge();
+        let mut save = save("exit");
+        save.load(&mut cartridge).unwrap();
+
+        save.flush_on_exit(&mut cartridge).unwrap();
+        assert_eq!(fs::read(save.path()).unwrap().len(), RTC_FOOTER_SIZE);
+
+        fs::remove_file(save.path()).unwrap();
+    }
+}
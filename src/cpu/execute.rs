@@ -1,36 +1,111 @@
-use super::{Cpu, instructions::*};
-use crate::bus::Bus;
+use super::{Cpu, MemoryInterface, instructions::*};
+use crate::interrupts::Interrupt;
+
+// every bus access takes one M-cycle
+fn read<M: MemoryInterface>(bus: &mut M, addr: u16) -> u8 {
+    let value = bus.read8(addr);
+    bus.tick_mcycle();
+
+    value
+}
+
+fn write<M: MemoryInterface>(bus: &mut M, addr: u16, value: u8) {
+    bus.write8(addr, value);
+    bus.tick_mcycle();
+}
 
 impl Cpu {
-    pub fn step(&mut self, bus: &mut Bus) -> u8 {
+    pub fn step<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        // nothing but a reset gets the CPU out of an illegal opcode, not
+        // even an interrupt
+        if self.locked {
+            bus.tick_mcycle();
+            return 4;
+        }
+
+        // HDMA and speed switches hold the CPU until they're done
+        if bus.cpu_stalled() {
+            bus.tick_mcycle();
+            return 4;
+        }
+
+        if self.stopped {
+            // STOP is only left once one of the selected joypad lines goes low
+            if bus.joypad_lines() == 0x0F {
+                bus.tick_mcycle();
+                return 4;
+            }
+
+            self.stopped = false;
+            bus.resume();
+        }
+
+        let pending = bus.pending_interrupts();
+
         if self.halted {
-            return 4; // HALT burns cycles
+            if pending == 0 {
+                bus.tick_mcycle();
+                return 4; // HALT burns cycles
+            }
+
+            // any pending interrupt wakes the CPU, even with IME=0
+            self.halted = false;
         }
 
-        let opcode = bus.read8(self.regs.pc);
-        self.regs.pc = self.regs.pc.wrapping_add(1);
+        if self.ime && pending != 0 {
+            return self.service_interrupt(bus);
+        }
+
+        // an EI from the previous step takes effect now, so the
+        // instruction right after EI always runs before any interrupt
+        if self.ime_pending {
+            self.ime_pending = false;
+            self.ime = true;
+        }
+
+        let opcode = read(bus, self.regs.pc);
+        if self.halt_bug {
+            // the byte after HALT gets read again on the next fetch
+            self.halt_bug = false;
+        } else {
+            self.regs.pc = self.regs.pc.wrapping_add(1);
+        }
 
         let instruction = decode(opcode);
         self.execute_instruction(instruction, bus)
     }
 
-    fn execute_instruction(&mut self, instr: Instruction, bus: &mut Bus) -> u8 {
+    fn service_interrupt<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let Some(interrupt) = Interrupt::highest_priority(bus.pending_interrupts()) else {
+            unreachable!("service_interrupt called with nothing pending");
+        };
+
+        // acknowledge the interrupt and disable further ones until RETI/EI
+        self.ime = false;
+        bus.acknowledge_interrupt(interrupt);
+
+        // two idle M-cycles, the push and one more to load the vector
+        bus.tick_mcycle();
+        self.push16(bus, self.regs.pc);
+        self.regs.pc = interrupt.vector();
+        bus.tick_mcycle();
+
+        20
+    }
+
+    fn execute_instruction<M: MemoryInterface>(&mut self, instr: Instruction, bus: &mut M) -> u8 {
         match instr {
             Instruction::NOP => 4,
             Instruction::LDBCD16 => self.ld_bc_d16(bus),
             Instruction::LDBCA => self.ld_bc_a(bus),
-            Instruction::INCBC => self.inc_bc(),
-            Instruction::DECB => self.dec_b(),
-            Instruction::INCHL => self.inc_hl(),
-            Instruction::INCH => self.inc_h(),
+            Instruction::INCBC => self.inc_bc(bus),
+            Instruction::INCHL => self.inc_hl(bus),
             Instruction::LDDED16 => self.ld_de_d16(bus),
-            Instruction::INCDE => self.inc_de(),
+            Instruction::INCDE => self.inc_de(bus),
             Instruction::LDADE => self.ld_a_de(bus),
             Instruction::JRZR8 => self.jr_z_r8(bus),
             Instruction::JRNZR8 => self.jr_nz_r8(bus),
             Instruction::LDAHLINC => self.ld_a_hlinc(bus),
-            Instruction::INCL => self.inc_l(),
-            Instruction::DECL => self.dec_l(),
             Instruction::ADD(target) => self.add(target),
             Instruction::LDIMM8(reg) => self.ld_imm8(reg, bus),
             Instruction::LDHLD16 => self.ld_hl_d16(bus),
@@ -77,16 +152,78 @@ impl Cpu {
             Instruction::POPAF => self.pop_af(bus),
             Instruction::PUSHAF => self.push_af(bus),
             Instruction::CPD8 => self.cp_d8(bus),
+            Instruction::INC(op) => self.inc(op, bus),
+            Instruction::DEC(op) => self.dec(op, bus),
+            Instruction::LDA16SP => self.ld_a16_sp(bus),
+            Instruction::LDSPHL => self.ld_sp_hl(bus),
+            Instruction::LDHLSPR8 => self.ld_hl_sp_r8(bus),
+            Instruction::ADDSPR8 => self.add_sp_r8(bus),
+            Instruction::INCSP => self.inc_sp(bus),
+            Instruction::DECBC => self.dec_bc(bus),
+            Instruction::DECDE => self.dec_de(bus),
+            Instruction::DECHL => self.dec_hl(bus),
+            Instruction::DECSP => self.dec_sp(bus),
+            Instruction::ADDHLBC => self.add_hl_rr(self.regs.get_bc(), bus),
+            Instruction::ADDHLDE => self.add_hl_rr(self.regs.get_de(), bus),
+            Instruction::ADDHLHL => self.add_hl_rr(self.regs.get_hl(), bus),
+            Instruction::ADDHLSP => self.add_hl_rr(self.regs.sp, bus),
+            Instruction::POPDE => self.pop_de(bus),
+            Instruction::LDABC => self.ld_a_bc(bus),
+            Instruction::LDDEA => self.ld_de_a(bus),
+            Instruction::LDAHLDEC => self.ld_a_hldec(bus),
+            Instruction::LDHLD8 => self.ld_hl_d8(bus),
+            Instruction::LDCA => self.ld_c_a(bus),
+            Instruction::LDAC => self.ld_a_c(bus),
+            Instruction::ADDAHL => self.add_a_hl(bus),
+            Instruction::ADC(op) => self.adc(op, bus),
+            Instruction::SUB(op) => self.sub(op, bus),
+            Instruction::SBC(op) => self.sbc(op, bus),
+            Instruction::AND(op) => self.and(op, bus),
+            Instruction::ADCD8 => self.adc_d8(bus),
+            Instruction::SBCD8 => self.sbc_d8(bus),
+            Instruction::XORD8 => self.xor_d8(bus),
+            Instruction::ORD8 => self.or_d8(bus),
+            Instruction::RLCA => self.rlca(),
+            Instruction::RRCA => self.rrca(),
+            Instruction::RLA => self.rla(),
+            Instruction::RRA => self.rra(),
+            Instruction::DAA => self.daa(),
+            Instruction::CPL => self.cpl(),
+            Instruction::SCF => self.scf(),
+            Instruction::CCF => self.ccf(),
+            Instruction::JRNCR8 => self.jr_cc_r8(!self.regs.get_c(), bus),
+            Instruction::JRCR8 => self.jr_cc_r8(self.regs.get_c(), bus),
+            Instruction::JPNZA16 => self.jp_cc_a16(!self.regs.get_z(), bus),
+            Instruction::JPZA16 => self.jp_cc_a16(self.regs.get_z(), bus),
+            Instruction::JPNCA16 => self.jp_cc_a16(!self.regs.get_c(), bus),
+            Instruction::JPCA16 => self.jp_cc_a16(self.regs.get_c(), bus),
+            Instruction::JPHL => self.jp_hl(),
+            Instruction::CALLZA16 => self.call_cc_a16(self.regs.get_z(), bus),
+            Instruction::CALLNCA16 => self.call_cc_a16(!self.regs.get_c(), bus),
+            Instruction::CALLCA16 => self.call_cc_a16(self.regs.get_c(), bus),
+            Instruction::RETNZ => self.ret_cc(!self.regs.get_z(), bus),
+            Instruction::RETZ => self.ret_cc(self.regs.get_z(), bus),
+            Instruction::RETNC => self.ret_cc(!self.regs.get_c(), bus),
+            Instruction::RETC => self.ret_cc(self.regs.get_c(), bus),
+            Instruction::RETI => self.reti(bus),
+            Instruction::RST(vector) => self.rst(vector, bus),
+            Instruction::EI => self.ei(),
+            Instruction::HALT => self.halt(bus),
+            Instruction::STOP => self.stop(bus),
+            Instruction::ILLEGAL(_) => {
+                self.locked = true;
+                4
+            }
         }
     }
 
-    fn ld_bc_d16(&mut self, bus: &mut Bus) -> u8 {
+    fn ld_bc_d16<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         // read 16-bit immediate (lil endian)
-        let lo = bus.read8(self.regs.pc) as u16;
+        let lo = read(bus, self.regs.pc) as u16;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         // then high
-        let hi = bus.read8(self.regs.pc) as u16;
+        let hi = read(bus, self.regs.pc) as u16;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         let value = (hi << 8) | lo;
@@ -96,64 +233,38 @@ impl Cpu {
         12
     }
 
-    fn ld_bc_a(&mut self, bus: &mut Bus) -> u8 {
+    fn ld_bc_a<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         let addr = self.regs.get_bc();
-        bus.write8(addr, self.regs.a);
+        write(bus, addr, self.regs.a);
 
         8
     }
 
-    fn inc_bc(&mut self) -> u8 {
+    fn inc_bc<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         let data = self.regs.get_bc();
         let result = data.wrapping_add(1);
 
         self.regs.set_bc(result);
+        bus.tick_mcycle();
 
         8
     }
 
-    fn dec_b(&mut self) -> u8 {
-        let orig_val = self.regs.b;
-        let result = self.regs.b.wrapping_sub(1);
-
-        self.regs.b = result;
-
-        // flags
-        self.regs.set_z(result == 0);
-        self.regs.set_n(true);
-        self.regs.set_h((orig_val & 0x0F) == 0x0F);
-
-        4
-    }
-
-    fn inc_hl(&mut self) -> u8 {
+    fn inc_hl<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         let data = self.regs.get_hl();
         let result = data.wrapping_add(1);
 
         self.regs.set_hl(result);
+        bus.tick_mcycle();
 
         8
     }
 
-    fn inc_h(&mut self) -> u8 {
-        let orig_val = self.regs.h;
-        let result = self.regs.h.wrapping_add(1);
-
-        self.regs.h = result;
-
-        // flags
-        self.regs.set_z(result == 0);
-        self.regs.set_n(false);
-        self.regs.set_h((orig_val & 0x0F) == 0x0F);
-
-        4
-    }
-
-    fn ld_de_d16(&mut self, bus: &mut Bus) -> u8 {
-        let lo = bus.read8(self.regs.pc) as u16;
+    fn ld_de_d16<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let lo = read(bus, self.regs.pc) as u16;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
-        let hi = bus.read8(self.regs.pc) as u16;
+        let hi = read(bus, self.regs.pc) as u16;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         let value = (hi << 8) | lo;
@@ -163,30 +274,32 @@ impl Cpu {
         12
     }
 
-    fn inc_de(&mut self) -> u8 {
+    fn inc_de<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         let data = self.regs.get_de();
         let result = data.wrapping_add(1);
 
         self.regs.set_de(result);
+        bus.tick_mcycle();
 
         8
     }
 
-    fn ld_a_de(&mut self, bus: &mut Bus) -> u8 {
-        self.regs.a = bus.read8(self.regs.get_de());
+    fn ld_a_de<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        self.regs.a = read(bus, self.regs.get_de());
 
         8
     }
 
-    fn jr_z_r8(&mut self, bus: &mut Bus) -> u8 {
+    fn jr_z_r8<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         // read signed 8-bit offset
-        let offset = bus.read8(self.regs.pc) as i8;
+        let offset = read(bus, self.regs.pc) as i8;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         // check Zero flag
         if self.regs.get_z() {
             // PC is already pointing to the next instruction
             self.regs.pc = self.regs.pc.wrapping_add(offset as u16);
+            bus.tick_mcycle();
 
             12
         } else {
@@ -195,54 +308,28 @@ impl Cpu {
     }
 
     // this can be read as - Jump if Zero flag is _not_ set
-    fn jr_nz_r8(&mut self, bus: &mut Bus) -> u8 {
-        let e = bus.read8(self.regs.pc) as i8;
+    fn jr_nz_r8<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let e = read(bus, self.regs.pc) as i8;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         if !self.regs.get_z() {
             self.regs.pc = self.regs.pc.wrapping_add(e as u16);
+            bus.tick_mcycle();
             12
         } else {
             8
         }
     }
 
-    fn ld_a_hlinc(&mut self, bus: &mut Bus) -> u8 {
+    fn ld_a_hlinc<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         let hl = self.regs.get_hl();
 
-        self.regs.a = bus.read8(hl);
+        self.regs.a = read(bus, hl);
         self.regs.set_hl(hl.wrapping_add(1));
 
         8
     }
 
-    fn inc_l(&mut self) -> u8 {
-        let orig_val = self.regs.c;
-        let result = self.regs.c.wrapping_add(1);
-        self.regs.c = result;
-
-        // flags
-        self.regs.set_z(result == 0);
-        self.regs.set_n(false);
-        self.regs.set_h((orig_val & 0x0F) == 0x0F);
-
-        4
-    }
-
-    fn dec_l(&mut self) -> u8 {
-        let l = self.regs.l;
-        let result = self.regs.l.wrapping_sub(1);
-
-        self.regs.l = result;
-
-        // flags
-        self.regs.set_z(result == 0);
-        self.regs.set_n(true);
-        self.regs.set_h((l & 0x0F) == 0x0F);
-
-        4
-    }
-
     fn add(&mut self, target: ArithmeticTarget) -> u8 {
         let value = match target {
             ArithmeticTarget::A => self.regs.a,
@@ -346,8 +433,8 @@ impl Cpu {
         4
     }
 
-    fn or_hl(&mut self, bus: &mut Bus) -> u8 {
-        let data = bus.read8(self.regs.get_hl());
+    fn or_hl<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let data = read(bus, self.regs.get_hl());
         let result = self.regs.a | data;
 
         self.regs.a = result;
@@ -362,8 +449,8 @@ impl Cpu {
     }
 
     fn or_a(&mut self) -> u8 {
-        let result = self.regs.a | self.regs.a;
-        self.regs.a = result;
+        // A | A leaves A unchanged, only the flags are affected
+        let result = self.regs.a;
 
         // flags
         self.regs.set_z(result == 0);
@@ -374,8 +461,8 @@ impl Cpu {
         4
     }
 
-    fn ld_imm8(&mut self, reg: Register8, bus: &mut Bus) -> u8 {
-        let value = bus.read8(self.regs.pc);
+    fn ld_imm8<M: MemoryInterface>(&mut self, reg: Register8, bus: &mut M) -> u8 {
+        let value = read(bus, self.regs.pc);
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         match reg {
@@ -391,12 +478,12 @@ impl Cpu {
         8
     }
 
-    fn ld_hl_d16(&mut self, bus: &mut Bus) -> u8 {
+    fn ld_hl_d16<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         // read 16-bit immediate (lil endian)
-        let lo = bus.read8(self.regs.pc) as u16;
+        let lo = read(bus, self.regs.pc) as u16;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
-        let hi = bus.read8(self.regs.pc) as u16;
+        let hi = read(bus, self.regs.pc) as u16;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         let value = (hi << 8) | lo;
@@ -405,27 +492,27 @@ impl Cpu {
         12
     }
 
-    fn ld_a16_a(&mut self, bus: &mut Bus) -> u8 {
+    fn ld_a16_a<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         // read 16-bit immediate (little endian)
-        let lo = bus.read8(self.regs.pc) as u16;
+        let lo = read(bus, self.regs.pc) as u16;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
-        let hi = bus.read8(self.regs.pc) as u16;
+        let hi = read(bus, self.regs.pc) as u16;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         let addr = (hi << 8) | lo;
 
-        bus.write8(addr, self.regs.a);
+        write(bus, addr, self.regs.a);
 
         16
     }
 
-    fn ld_sp_d16(&mut self, bus: &mut Bus) -> u8 {
+    fn ld_sp_d16<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         // read 16-bit immediate address (little endian)
-        let lo = bus.read8(self.regs.pc) as u16;
+        let lo = read(bus, self.regs.pc) as u16;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
-        let hi = bus.read8(self.regs.pc) as u16;
+        let hi = read(bus, self.regs.pc) as u16;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         let value = (hi << 8) | lo;
@@ -435,69 +522,70 @@ impl Cpu {
         12
     }
 
-    fn ld_hlpos_a(&mut self, bus: &mut Bus) -> u8 {
+    fn ld_hlpos_a<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         let hl = self.regs.get_hl();
-        bus.write8(hl, self.regs.a);
+        write(bus, hl, self.regs.a);
 
         self.regs.set_hl(hl.wrapping_add(1));
 
         8
     }
 
-    fn ld_hlneg_a(&mut self, bus: &mut Bus) -> u8 {
+    fn ld_hlneg_a<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         let hl = self.regs.get_hl();
-        bus.write8(hl, self.regs.a);
+        write(bus, hl, self.regs.a);
 
         self.regs.set_hl(hl.wrapping_sub(1));
 
         8
     }
 
-    fn ld_a8_a(&mut self, bus: &mut Bus) -> u8 {
-        let offset = bus.read8(self.regs.pc) as u16;
+    fn ld_a8_a<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let offset = read(bus, self.regs.pc) as u16;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         let addr = 0xFF00 | offset;
 
-        bus.write8(addr, self.regs.a);
+        write(bus, addr, self.regs.a);
 
         12
     }
 
-    fn ld_a_a16(&mut self, bus: &mut Bus) -> u8 {
+    fn ld_a_a16<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         // read 16-bit immediate address (little endian)
-        let lo = bus.read8(self.regs.pc) as u16;
+        let lo = read(bus, self.regs.pc) as u16;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
-        let hi = bus.read8(self.regs.pc) as u16;
+        let hi = read(bus, self.regs.pc) as u16;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         let addr = (hi << 8) | lo;
 
         // read from memory into A
-        self.regs.a = bus.read8(addr);
+        self.regs.a = read(bus, addr);
 
         16
     }
 
-    fn jr_r8(&mut self, bus: &mut Bus) -> u8 {
+    fn jr_r8<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         // read signed 8-bit offset
-        let offset = bus.read8(self.regs.pc) as i8;
+        let offset = read(bus, self.regs.pc) as i8;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         // PC-relative jump
 
         self.regs.pc = self.regs.pc.wrapping_add(offset as u16);
+        bus.tick_mcycle();
 
         12
     }
 
-    fn ld_reg_reg(&mut self, dst: Operand8, src: Operand8, bus: &mut Bus) -> u8 {
+    fn ld_reg_reg<M: MemoryInterface>(&mut self, dst: Operand8, src: Operand8, bus: &mut M) -> u8 {
         let value = match src {
             Operand8::Reg(r) => self.regs.read_reg8(r),
             Operand8::IndHL => {
                 let addr = self.regs.get_hl();
-                bus.read8(addr)
+                read(bus, addr)
             }
         };
 
@@ -505,7 +593,7 @@ impl Cpu {
             Operand8::Reg(r) => self.regs.write_reg8(r, value),
             Operand8::IndHL => {
                 let addr = self.regs.get_hl();
-                bus.write8(addr, value)
+                write(bus, addr, value)
             }
         }
 
@@ -517,12 +605,12 @@ impl Cpu {
         }
     }
 
-    fn cp(&mut self, op: Operand8, bus: &Bus) -> u8 {
+    fn cp<M: MemoryInterface>(&mut self, op: Operand8, bus: &mut M) -> u8 {
         let value = match op {
             Operand8::Reg(r) => self.regs.read_reg8(r),
             Operand8::IndHL => {
                 let addr = self.regs.get_hl();
-                bus.read8(addr)
+                read(bus, addr)
             }
         };
 
@@ -622,8 +710,8 @@ impl Cpu {
         4
     }
 
-    fn xor_hl(&mut self, bus: &mut Bus) -> u8 {
-        let data = bus.read8(self.regs.get_hl());
+    fn xor_hl<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let data = read(bus, self.regs.get_hl());
         let result = self.regs.a ^ data;
 
         self.regs.a = result;
@@ -638,7 +726,8 @@ impl Cpu {
     }
 
     fn xor_a(&mut self) -> u8 {
-        let result = self.regs.a ^ self.regs.a;
+        // A ^ A is always zero
+        let result = 0;
         self.regs.a = result;
 
         // flags
@@ -650,11 +739,11 @@ impl Cpu {
         4
     }
 
-    fn pop_bc(&mut self, bus: &mut Bus) -> u8 {
-        let lo = bus.read8(self.regs.sp) as u16;
+    fn pop_bc<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let lo = read(bus, self.regs.sp) as u16;
         self.regs.sp = self.regs.sp.wrapping_add(1);
 
-        let hi = bus.read8(self.regs.sp) as u16;
+        let hi = read(bus, self.regs.sp) as u16;
         self.regs.sp = self.regs.sp.wrapping_add(1);
 
         let value = (hi << 8) | lo;
@@ -663,27 +752,28 @@ impl Cpu {
         12
     }
 
-    fn jp_a16(&mut self, bus: &mut Bus) -> u8 {
+    fn jp_a16<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         // read 16-bit immediate (lil endian)
-        let lo = bus.read8(self.regs.pc) as u16;
+        let lo = read(bus, self.regs.pc) as u16;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
-        let hi = bus.read8(self.regs.pc) as u16;
+        let hi = read(bus, self.regs.pc) as u16;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         let addr = (hi << 8) | lo;
 
         // jump
         self.regs.pc = addr;
+        bus.tick_mcycle();
 
         16
     }
 
-    fn call_nz_a16(&mut self, bus: &mut Bus) -> u8 {
-        let lo = bus.read8(self.regs.pc) as u16;
+    fn call_nz_a16<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let lo = read(bus, self.regs.pc) as u16;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
-        let hi = bus.read8(self.regs.pc) as u16;
+        let hi = read(bus, self.regs.pc) as u16;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         let target = (hi << 8) | lo;
@@ -692,13 +782,7 @@ impl Cpu {
         let ret = self.regs.pc;
 
         if !self.regs.get_z() {
-            // push high byte first
-            self.regs.sp = self.regs.sp.wrapping_sub(1);
-            bus.write8(self.regs.sp, (ret >> 8) as u8);
-
-            // then low byte
-            self.regs.sp = self.regs.sp.wrapping_sub(1);
-            bus.write8(self.regs.sp, (ret & 0xFF) as u8);
+            self.push16(bus, ret);
 
             // jump
             self.regs.pc = target;
@@ -709,24 +793,16 @@ impl Cpu {
         }
     }
 
-    fn push_bc(&mut self, bus: &mut Bus) -> u8 {
-        let b = self.regs.b;
-        let c = self.regs.c;
-
-        // push high byte first
-        self.regs.sp = self.regs.sp.wrapping_sub(1);
-        bus.write8(self.regs.sp, b);
-
-        // then low byte
-        self.regs.sp = self.regs.sp.wrapping_sub(1);
-        bus.write8(self.regs.sp, c);
+    fn push_bc<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let value = self.regs.get_bc();
+        self.push16(bus, value);
 
         16
     }
 
-    fn add_a_d8(&mut self, bus: &mut Bus) -> u8 {
+    fn add_a_d8<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         let reg_a = self.regs.a;
-        let n = bus.read8(self.regs.pc);
+        let n = read(bus, self.regs.pc);
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         let result = reg_a.wrapping_add(n);
@@ -741,24 +817,16 @@ impl Cpu {
         8
     }
 
-    fn push_de(&mut self, bus: &mut Bus) -> u8 {
-        let d = self.regs.d;
-        let e = self.regs.e;
-
-        // push high byte first
-        self.regs.sp = self.regs.sp.wrapping_sub(1);
-        bus.write8(self.regs.sp, d);
-
-        // then low
-        self.regs.sp = self.regs.sp.wrapping_sub(1);
-        bus.write8(self.regs.sp, e);
+    fn push_de<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let value = self.regs.get_de();
+        self.push16(bus, value);
 
         16
     }
 
-    fn sub_d8(&mut self, bus: &mut Bus) -> u8 {
+    fn sub_d8<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         let a = self.regs.a;
-        let n = bus.read8(self.regs.pc);
+        let n = read(bus, self.regs.pc);
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         let result = a.wrapping_sub(n);
@@ -768,31 +836,23 @@ impl Cpu {
         self.regs.set_z(result == 0);
         self.regs.set_n(true);
         self.regs.set_h((a & 0x0F) < (n & 0x0F));
-        self.regs.set_c((a & 0xFF) < (n & 0xFF));
+        self.regs.set_c(a < n);
 
         8
     }
 
-    fn call_a16(&mut self, bus: &mut Bus) -> u8 {
+    fn call_a16<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         // read target address (lil endian)
-        let lo = bus.read8(self.regs.pc) as u16;
+        let lo = read(bus, self.regs.pc) as u16;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
-        let hi = bus.read8(self.regs.pc) as u16;
+        let hi = read(bus, self.regs.pc) as u16;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         let target = (hi << 8) | lo;
 
         // push return address (PC after operands)
-        let ret = self.regs.pc;
-
-        // push high byte first
-        self.regs.sp = self.regs.sp.wrapping_sub(1);
-        bus.write8(self.regs.sp, (ret >> 8) as u8);
-
-        // then low byte
-        self.regs.sp = self.regs.sp.wrapping_sub(1);
-        bus.write8(self.regs.sp, (ret & 0xFF) as u8);
+        self.push16(bus, self.regs.pc);
 
         // jump
         self.regs.pc = target;
@@ -800,8 +860,8 @@ impl Cpu {
         24
     }
 
-    fn and_d8(&mut self, bus: &mut Bus) -> u8 {
-        let n = bus.read8(self.regs.pc);
+    fn and_d8<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let n = read(bus, self.regs.pc);
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         let result = self.regs.a & n;
@@ -815,13 +875,13 @@ impl Cpu {
         8
     }
 
-    fn pop_hl(&mut self, bus: &mut Bus) -> u8 {
+    fn pop_hl<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         // pop low byte
-        let lo = bus.read8(self.regs.sp) as u16;
+        let lo = read(bus, self.regs.sp) as u16;
         self.regs.sp = self.regs.sp.wrapping_add(1);
 
         // then high byte
-        let hi = bus.read8(self.regs.sp) as u16;
+        let hi = read(bus, self.regs.sp) as u16;
         self.regs.sp = self.regs.sp.wrapping_add(1);
 
         let value = (hi << 8) | lo;
@@ -831,23 +891,15 @@ impl Cpu {
         12
     }
 
-    fn push_hl(&mut self, bus: &mut Bus) -> u8 {
-        let h = self.regs.h;
-        let l = self.regs.l;
-
-        // push high byte first
-        self.regs.sp = self.regs.sp.wrapping_sub(1);
-        bus.write8(self.regs.sp, h);
-
-        // then low byte
-        self.regs.sp = self.regs.sp.wrapping_sub(1);
-        bus.write8(self.regs.sp, l);
+    fn push_hl<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let value = self.regs.get_hl();
+        self.push16(bus, value);
 
         16
     }
 
-    fn prefix_cb(&mut self, bus: &mut Bus) -> u8 {
-        let opcode = bus.read8(self.regs.pc);
+    fn prefix_cb<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let opcode = read(bus, self.regs.pc);
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         let x = (opcode & 0b11000000) >> 6;
@@ -863,18 +915,19 @@ impl Cpu {
         }
     }
 
-    fn ret(&mut self, bus: &mut Bus) -> u8 {
+    fn ret<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         // pop low byte
-        let lo = bus.read8(self.regs.sp) as u16;
+        let lo = read(bus, self.regs.sp) as u16;
         self.regs.sp = self.regs.sp.wrapping_add(1);
 
         // pop high byte
-        let hi = bus.read8(self.regs.sp) as u16;
+        let hi = read(bus, self.regs.sp) as u16;
         self.regs.sp = self.regs.sp.wrapping_add(1);
 
         let addr = (hi << 8) | lo;
 
         self.regs.pc = addr;
+        bus.tick_mcycle();
 
         16
     }
@@ -884,25 +937,25 @@ impl Cpu {
         4
     }
 
-    fn ldh_a_a8(&mut self, bus: &mut Bus) -> u8 {
-        let lo = bus.read8(self.regs.pc) as u16;
+    fn ldh_a_a8<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let lo = read(bus, self.regs.pc) as u16;
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         let hi = 0xFF;
         let addr = (hi << 8) | lo;
 
-        self.regs.a = bus.read8(addr);
+        self.regs.a = read(bus, addr);
 
         12
     }
 
-    fn pop_af(&mut self, bus: &mut Bus) -> u8 {
+    fn pop_af<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
         // pop lower byte
-        let lo = bus.read8(self.regs.sp) as u16;
+        let lo = read(bus, self.regs.sp) as u16;
         self.regs.sp = self.regs.sp.wrapping_add(1);
 
         // pop high byte
-        let hi = bus.read8(self.regs.sp) as u16;
+        let hi = read(bus, self.regs.sp) as u16;
         self.regs.sp = self.regs.sp.wrapping_add(1);
 
         let value = (hi << 8) | lo;
@@ -911,26 +964,19 @@ impl Cpu {
         12
     }
 
-    fn push_af(&mut self, bus: &mut Bus) -> u8 {
-        let a = self.regs.a;
-        let f = self.regs.f;
+    fn push_af<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let af = self.regs.get_af();
 
         // because F is the flags reg (aka restricted), ensure bits 3 to 0 are cleared
-        let cleared_f = f & !0x0F;
+        let cleared_af = af & !0x0F;
 
-        // first push high byte
-        self.regs.sp = self.regs.sp.wrapping_sub(1);
-        bus.write8(self.regs.sp, a);
-
-        // then low byte
-        self.regs.sp = self.regs.sp.wrapping_sub(1);
-        bus.write8(self.regs.sp, cleared_f);
+        self.push16(bus, cleared_af);
 
         16
     }
 
-    fn cp_d8(&mut self, bus: &mut Bus) -> u8 {
-        let n = bus.read8(self.regs.pc); // read the immediate data
+    fn cp_d8<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let n = read(bus, self.regs.pc); // read the immediate data
         self.regs.pc = self.regs.pc.wrapping_add(1);
 
         let reg_a = self.regs.a;
@@ -940,23 +986,676 @@ impl Cpu {
         self.regs.set_z(result == 0);
         self.regs.set_n(true);
         self.regs.set_h((reg_a & 0x0F) < (n & 0x0F));
-        self.regs.set_c((reg_a & 0xFF) < (n & 0xFF));
+        self.regs.set_c(reg_a < n);
 
         8
     }
 
+    fn inc<M: MemoryInterface>(&mut self, op: Operand8, bus: &mut M) -> u8 {
+        let value = self.read_operand8(op, bus);
+        let result = value.wrapping_add(1);
+
+        self.write_operand8(op, bus, result);
+
+        // flags - carry is unchanged
+        self.regs.set_z(result == 0);
+        self.regs.set_n(false);
+        self.regs.set_h((value & 0x0F) == 0x0F);
+
+        match op {
+            Operand8::Reg(_) => 4,
+            Operand8::IndHL => 12,
+        }
+    }
+
+    fn dec<M: MemoryInterface>(&mut self, op: Operand8, bus: &mut M) -> u8 {
+        let value = self.read_operand8(op, bus);
+        let result = value.wrapping_sub(1);
+
+        self.write_operand8(op, bus, result);
+
+        // flags - carry is unchanged
+        self.regs.set_z(result == 0);
+        self.regs.set_n(true);
+        self.regs.set_h((value & 0x0F) == 0x00);
+
+        match op {
+            Operand8::Reg(_) => 4,
+            Operand8::IndHL => 12,
+        }
+    }
+
+    fn ld_a16_sp<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let addr = self.fetch16(bus);
+        let sp = self.regs.sp;
+
+        // stored little endian
+        write(bus, addr, (sp & 0xFF) as u8);
+        write(bus, addr.wrapping_add(1), (sp >> 8) as u8);
+
+        20
+    }
+
+    fn ld_sp_hl<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        self.regs.sp = self.regs.get_hl();
+        bus.tick_mcycle();
+
+        8
+    }
+
+    fn ld_hl_sp_r8<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let result = self.sp_plus_r8(bus);
+        self.regs.set_hl(result);
+        bus.tick_mcycle();
+
+        12
+    }
+
+    fn add_sp_r8<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        self.regs.sp = self.sp_plus_r8(bus);
+        bus.tick_mcycle();
+        bus.tick_mcycle();
+
+        16
+    }
+
+    // shared by ADD SP, r8 and LD HL, SP+r8
+    fn sp_plus_r8<M: MemoryInterface>(&mut self, bus: &mut M) -> u16 {
+        let offset = self.fetch8(bus);
+        let sp = self.regs.sp;
+
+        // NOTE: H and C come from the unsigned add on the low byte,
+        // even though the offset itself is signed
+        self.regs.set_z(false);
+        self.regs.set_n(false);
+        self.regs.set_h((sp & 0x0F) + (offset as u16 & 0x0F) > 0x0F);
+        self.regs.set_c((sp & 0xFF) + offset as u16 > 0xFF);
+
+        sp.wrapping_add(offset as i8 as u16)
+    }
+
+    fn inc_sp<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        self.regs.sp = self.regs.sp.wrapping_add(1);
+        bus.tick_mcycle();
+
+        8
+    }
+
+    fn dec_bc<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let result = self.regs.get_bc().wrapping_sub(1);
+        self.regs.set_bc(result);
+        bus.tick_mcycle();
+
+        8
+    }
+
+    fn dec_de<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let result = self.regs.get_de().wrapping_sub(1);
+        self.regs.set_de(result);
+        bus.tick_mcycle();
+
+        8
+    }
+
+    fn dec_hl<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let result = self.regs.get_hl().wrapping_sub(1);
+        self.regs.set_hl(result);
+        bus.tick_mcycle();
+
+        8
+    }
+
+    fn dec_sp<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        bus.tick_mcycle();
+
+        8
+    }
+
+    fn add_hl_rr<M: MemoryInterface>(&mut self, value: u16, bus: &mut M) -> u8 {
+        let hl = self.regs.get_hl();
+        let result = hl.wrapping_add(value);
+
+        // flags - zero is unchanged, half carry is from bit 11
+        self.regs.set_n(false);
+        self.regs.set_h((hl & 0x0FFF) + (value & 0x0FFF) > 0x0FFF);
+        self.regs.set_c(hl as u32 + value as u32 > 0xFFFF);
+
+        self.regs.set_hl(result);
+        bus.tick_mcycle();
+
+        8
+    }
+
+    fn pop_de<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let value = self.pop16(bus);
+        self.regs.set_de(value);
+
+        12
+    }
+
+    fn ld_a_bc<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        self.regs.a = read(bus, self.regs.get_bc());
+
+        8
+    }
+
+    fn ld_de_a<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        write(bus, self.regs.get_de(), self.regs.a);
+
+        8
+    }
+
+    fn ld_a_hldec<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let hl = self.regs.get_hl();
+
+        self.regs.a = read(bus, hl);
+        self.regs.set_hl(hl.wrapping_sub(1));
+
+        8
+    }
+
+    fn ld_hl_d8<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let value = self.fetch8(bus);
+        write(bus, self.regs.get_hl(), value);
+
+        12
+    }
+
+    fn ld_c_a<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let addr = 0xFF00 | self.regs.c as u16;
+        write(bus, addr, self.regs.a);
+
+        8
+    }
+
+    fn ld_a_c<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let addr = 0xFF00 | self.regs.c as u16;
+        self.regs.a = read(bus, addr);
+
+        8
+    }
+
+    fn add_a_hl<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let value = read(bus, self.regs.get_hl());
+        self.alu_add(value, false);
+
+        8
+    }
+
+    fn adc<M: MemoryInterface>(&mut self, op: Operand8, bus: &mut M) -> u8 {
+        let value = self.read_operand8(op, bus);
+        self.alu_add(value, self.regs.get_c());
+
+        match op {
+            Operand8::Reg(_) => 4,
+            Operand8::IndHL => 8,
+        }
+    }
+
+    fn sub<M: MemoryInterface>(&mut self, op: Operand8, bus: &mut M) -> u8 {
+        let value = self.read_operand8(op, bus);
+        self.alu_sub(value, false);
+
+        match op {
+            Operand8::Reg(_) => 4,
+            Operand8::IndHL => 8,
+        }
+    }
+
+    fn sbc<M: MemoryInterface>(&mut self, op: Operand8, bus: &mut M) -> u8 {
+        let value = self.read_operand8(op, bus);
+        self.alu_sub(value, self.regs.get_c());
+
+        match op {
+            Operand8::Reg(_) => 4,
+            Operand8::IndHL => 8,
+        }
+    }
+
+    fn and<M: MemoryInterface>(&mut self, op: Operand8, bus: &mut M) -> u8 {
+        let value = self.read_operand8(op, bus);
+        let result = self.regs.a & value;
+        self.regs.a = result;
+
+        // flags - AND always sets half carry
+        self.regs.set_z(result == 0);
+        self.regs.set_n(false);
+        self.regs.set_h(true);
+        self.regs.set_c(false);
+
+        match op {
+            Operand8::Reg(_) => 4,
+            Operand8::IndHL => 8,
+        }
+    }
+
+    fn adc_d8<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let n = self.fetch8(bus);
+        self.alu_add(n, self.regs.get_c());
+
+        8
+    }
+
+    fn sbc_d8<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let n = self.fetch8(bus);
+        self.alu_sub(n, self.regs.get_c());
+
+        8
+    }
+
+    fn xor_d8<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let n = self.fetch8(bus);
+        let result = self.regs.a ^ n;
+        self.regs.a = result;
+
+        // flags
+        self.regs.set_z(result == 0);
+        self.regs.set_n(false);
+        self.regs.set_h(false);
+        self.regs.set_c(false);
+
+        8
+    }
+
+    fn or_d8<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let n = self.fetch8(bus);
+        let result = self.regs.a | n;
+        self.regs.a = result;
+
+        // flags
+        self.regs.set_z(result == 0);
+        self.regs.set_n(false);
+        self.regs.set_h(false);
+        self.regs.set_c(false);
+
+        8
+    }
+
+    // A = A + value (+ carry), used by ADD and ADC
+    fn alu_add(&mut self, value: u8, carry_in: bool) {
+        let a = self.regs.a;
+        let carry = carry_in as u8;
+        let result = a.wrapping_add(value).wrapping_add(carry);
+
+        // flags
+        self.regs.set_z(result == 0);
+        self.regs.set_n(false);
+        self.regs.set_h((a & 0x0F) + (value & 0x0F) + carry > 0x0F);
+        self.regs
+            .set_c(a as u16 + value as u16 + carry as u16 > 0xFF);
+
+        self.regs.a = result;
+    }
+
+    // A = A - value (- carry), used by SUB and SBC
+    fn alu_sub(&mut self, value: u8, carry_in: bool) {
+        let a = self.regs.a;
+        let carry = carry_in as u8;
+        let result = a.wrapping_sub(value).wrapping_sub(carry);
+
+        // flags
+        self.regs.set_z(result == 0);
+        self.regs.set_n(true);
+        self.regs.set_h((a & 0x0F) < (value & 0x0F) + carry);
+        self.regs.set_c((a as u16) < value as u16 + carry as u16);
+
+        self.regs.a = result;
+    }
+
+    // NOTE: unlike their CB counterparts, the A-register rotates
+    // always clear the zero flag
+    fn rlca(&mut self) -> u8 {
+        let a = self.regs.a;
+        self.regs.a = a.rotate_left(1);
+
+        self.regs.set_z(false);
+        self.regs.set_n(false);
+        self.regs.set_h(false);
+        self.regs.set_c(a & 0x80 != 0);
+
+        4
+    }
+
+    fn rrca(&mut self) -> u8 {
+        let a = self.regs.a;
+        self.regs.a = a.rotate_right(1);
+
+        self.regs.set_z(false);
+        self.regs.set_n(false);
+        self.regs.set_h(false);
+        self.regs.set_c(a & 0x01 != 0);
+
+        4
+    }
+
+    fn rla(&mut self) -> u8 {
+        let a = self.regs.a;
+        self.regs.a = (a << 1) | self.regs.get_c() as u8;
+
+        self.regs.set_z(false);
+        self.regs.set_n(false);
+        self.regs.set_h(false);
+        self.regs.set_c(a & 0x80 != 0);
+
+        4
+    }
+
+    fn rra(&mut self) -> u8 {
+        let a = self.regs.a;
+        self.regs.a = (a >> 1) | ((self.regs.get_c() as u8) << 7);
+
+        self.regs.set_z(false);
+        self.regs.set_n(false);
+        self.regs.set_h(false);
+        self.regs.set_c(a & 0x01 != 0);
+
+        4
+    }
+
+    // decimal adjust A after a BCD add or subtract
+    fn daa(&mut self) -> u8 {
+        let mut a = self.regs.a;
+        let mut carry = self.regs.get_c();
+
+        if !self.regs.get_n() {
+            // after an addition, adjust if a digit overflowed or is out of range
+            if carry || a > 0x99 {
+                a = a.wrapping_add(0x60);
+                carry = true;
+            }
+            if self.regs.get_h() || (a & 0x0F) > 0x09 {
+                a = a.wrapping_add(0x06);
+            }
+        } else {
+            // after a subtraction, only adjust if there was a borrow
+            if carry {
+                a = a.wrapping_sub(0x60);
+            }
+            if self.regs.get_h() {
+                a = a.wrapping_sub(0x06);
+            }
+        }
+
+        self.regs.a = a;
+
+        // flags - N is unchanged
+        self.regs.set_z(a == 0);
+        self.regs.set_h(false);
+        self.regs.set_c(carry);
+
+        4
+    }
+
+    fn cpl(&mut self) -> u8 {
+        self.regs.a = !self.regs.a;
+
+        self.regs.set_n(true);
+        self.regs.set_h(true);
+
+        4
+    }
+
+    fn scf(&mut self) -> u8 {
+        self.regs.set_n(false);
+        self.regs.set_h(false);
+        self.regs.set_c(true);
+
+        4
+    }
+
+    fn ccf(&mut self) -> u8 {
+        let carry = self.regs.get_c();
+
+        self.regs.set_n(false);
+        self.regs.set_h(false);
+        self.regs.set_c(!carry);
+
+        4
+    }
+
+    fn jr_cc_r8<M: MemoryInterface>(&mut self, condition: bool, bus: &mut M) -> u8 {
+        let offset = self.fetch8(bus) as i8;
+
+        if condition {
+            self.regs.pc = self.regs.pc.wrapping_add(offset as u16);
+            bus.tick_mcycle();
+            12
+        } else {
+            8
+        }
+    }
+
+    fn jp_cc_a16<M: MemoryInterface>(&mut self, condition: bool, bus: &mut M) -> u8 {
+        let addr = self.fetch16(bus);
+
+        if condition {
+            self.regs.pc = addr;
+            bus.tick_mcycle();
+            16
+        } else {
+            12
+        }
+    }
+
+    fn jp_hl(&mut self) -> u8 {
+        self.regs.pc = self.regs.get_hl();
+
+        4
+    }
+
+    fn call_cc_a16<M: MemoryInterface>(&mut self, condition: bool, bus: &mut M) -> u8 {
+        let target = self.fetch16(bus);
+
+        if condition {
+            // push return address (PC after operands)
+            self.push16(bus, self.regs.pc);
+            self.regs.pc = target;
+            24
+        } else {
+            12
+        }
+    }
+
+    fn ret_cc<M: MemoryInterface>(&mut self, condition: bool, bus: &mut M) -> u8 {
+        // the condition is checked in an M-cycle of its own
+        bus.tick_mcycle();
+
+        if condition {
+            self.regs.pc = self.pop16(bus);
+            bus.tick_mcycle();
+            20
+        } else {
+            8
+        }
+    }
+
+    fn reti<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        self.regs.pc = self.pop16(bus);
+        self.ime = true;
+        bus.tick_mcycle();
+
+        16
+    }
+
+    fn rst<M: MemoryInterface>(&mut self, vector: u16, bus: &mut M) -> u8 {
+        self.push16(bus, self.regs.pc);
+        self.regs.pc = vector;
+
+        16
+    }
+
+    fn ei(&mut self) -> u8 {
+        // delayed by one instruction, see step()
+        self.ime_pending = true;
+        4
+    }
+
+    fn halt<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        if !self.ime && bus.pending_interrupts() != 0 {
+            // HALT bug: the CPU doesn't halt at all and instead
+            // reads the next byte twice
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
+
+        4
+    }
+
+    fn stop<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        // STOP is encoded as two bytes (0x10 0x00), the second one is
+        // skipped without being read
+        self.regs.pc = self.regs.pc.wrapping_add(1);
+
+        // on CGB an armed KEY1 makes this a speed switch rather than a stop
+        if !bus.stop() {
+            self.stopped = true;
+        }
+
+        4
+    }
+
+    // === Fetch / stack helpers === //
+    fn fetch8<M: MemoryInterface>(&mut self, bus: &mut M) -> u8 {
+        let value = read(bus, self.regs.pc);
+        self.regs.pc = self.regs.pc.wrapping_add(1);
+
+        value
+    }
+
+    // reads a little endian 16-bit immediate
+    fn fetch16<M: MemoryInterface>(&mut self, bus: &mut M) -> u16 {
+        let lo = self.fetch8(bus) as u16;
+        let hi = self.fetch8(bus) as u16;
+
+        (hi << 8) | lo
+    }
+
+    // every push starts with an idle M-cycle while SP is decremented
+    fn push16<M: MemoryInterface>(&mut self, bus: &mut M, value: u16) {
+        bus.tick_mcycle();
+
+        // push high byte first
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        write(bus, self.regs.sp, (value >> 8) as u8);
+
+        // then low byte
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        write(bus, self.regs.sp, (value & 0xFF) as u8);
+    }
+
+    fn pop16<M: MemoryInterface>(&mut self, bus: &mut M) -> u16 {
+        let lo = read(bus, self.regs.sp) as u16;
+        self.regs.sp = self.regs.sp.wrapping_add(1);
+
+        let hi = read(bus, self.regs.sp) as u16;
+        self.regs.sp = self.regs.sp.wrapping_add(1);
+
+        (hi << 8) | lo
+    }
+
+    // Operand8 helpers
+    fn read_operand8<M: MemoryInterface>(&self, op: Operand8, bus: &mut M) -> u8 {
+        match op {
+            Operand8::Reg(r) => self.regs.read_reg8(r),
+            Operand8::IndHL => read(bus, self.regs.get_hl()),
+        }
+    }
+
+    fn write_operand8<M: MemoryInterface>(&mut self, op: Operand8, bus: &mut M, value: u8) {
+        match op {
+            Operand8::Reg(r) => self.regs.write_reg8(r, value),
+            Operand8::IndHL => write(bus, self.regs.get_hl(), value),
+        }
+    }
+
     // === CB functions === //
-    fn cb_rot_shift(&mut self, y: u8, z: u8, bus: &mut Bus) -> u8 {
+    fn cb_rot_shift<M: MemoryInterface>(&mut self, y: u8, z: u8, bus: &mut M) -> u8 {
         match y {
+            0 => self.rlc(z, bus),
+            1 => self.rrc(z, bus),
+            2 => self.rl(z, bus),
+            3 => self.rr(z, bus),
             4 => self.sla(z, bus),
             5 => self.sra(z, bus),
             6 => self.swap(z, bus),
             7 => self.srl(z, bus),
-            _ => todo!("Other rotate/shift function not implemented"),
+            _ => unreachable!(),
         }
     }
 
-    fn sra(&mut self, z: u8, bus: &mut Bus) -> u8 {
+    fn rlc<M: MemoryInterface>(&mut self, z: u8, bus: &mut M) -> u8 {
+        let value = self.cb_read_target(z, bus);
+
+        let carry = value & 0x80;
+        let result = value.rotate_left(1);
+
+        self.cb_write_target(z, bus, result);
+
+        // flags
+        self.regs.set_z(result == 0);
+        self.regs.set_n(false);
+        self.regs.set_h(false);
+        self.regs.set_c(carry != 0);
+
+        if z == 6 { 16 } else { 8 }
+    }
+
+    fn rrc<M: MemoryInterface>(&mut self, z: u8, bus: &mut M) -> u8 {
+        let value = self.cb_read_target(z, bus);
+
+        let carry = value & 0x01;
+        let result = value.rotate_right(1);
+
+        self.cb_write_target(z, bus, result);
+
+        // flags
+        self.regs.set_z(result == 0);
+        self.regs.set_n(false);
+        self.regs.set_h(false);
+        self.regs.set_c(carry != 0);
+
+        if z == 6 { 16 } else { 8 }
+    }
+
+    fn rl<M: MemoryInterface>(&mut self, z: u8, bus: &mut M) -> u8 {
+        let value = self.cb_read_target(z, bus);
+
+        // rotate through carry: old carry goes into bit 0
+        let carry = value & 0x80;
+        let result = (value << 1) | self.regs.get_c() as u8;
+
+        self.cb_write_target(z, bus, result);
+
+        // flags
+        self.regs.set_z(result == 0);
+        self.regs.set_n(false);
+        self.regs.set_h(false);
+        self.regs.set_c(carry != 0);
+
+        if z == 6 { 16 } else { 8 }
+    }
+
+    fn rr<M: MemoryInterface>(&mut self, z: u8, bus: &mut M) -> u8 {
+        let value = self.cb_read_target(z, bus);
+
+        // rotate through carry: old carry goes into bit 7
+        let carry = value & 0x01;
+        let result = (value >> 1) | ((self.regs.get_c() as u8) << 7);
+
+        self.cb_write_target(z, bus, result);
+
+        // flags
+        self.regs.set_z(result == 0);
+        self.regs.set_n(false);
+        self.regs.set_h(false);
+        self.regs.set_c(carry != 0);
+
+        if z == 6 { 16 } else { 8 }
+    }
+
+    fn sra<M: MemoryInterface>(&mut self, z: u8, bus: &mut M) -> u8 {
         let value = self.cb_read_target(z, bus);
 
         let carry = value & 0x01;
@@ -974,7 +1673,7 @@ impl Cpu {
         if z == 6 { 16 } else { 8 }
     }
 
-    fn swap(&mut self, z: u8, bus: &mut Bus) -> u8 {
+    fn swap<M: MemoryInterface>(&mut self, z: u8, bus: &mut M) -> u8 {
         let value = self.cb_read_target(z, bus);
 
         let upper = value & 0xF0;
@@ -993,7 +1692,7 @@ impl Cpu {
         if z == 6 { 16 } else { 8 }
     }
 
-    fn sla(&mut self, z: u8, bus: &mut Bus) -> u8 {
+    fn sla<M: MemoryInterface>(&mut self, z: u8, bus: &mut M) -> u8 {
         let value = self.cb_read_target(z, bus);
 
         let carry = value & 0x80;
@@ -1011,7 +1710,7 @@ impl Cpu {
         if z == 6 { 16 } else { 8 }
     }
 
-    fn srl(&mut self, z: u8, bus: &mut Bus) -> u8 {
+    fn srl<M: MemoryInterface>(&mut self, z: u8, bus: &mut M) -> u8 {
         let value = self.cb_read_target(z, bus);
 
         let carry = value & 0x01;
@@ -1030,7 +1729,7 @@ impl Cpu {
     }
 
     // CB Table Helpers
-    fn cb_read_target(&self, z: u8, bus: &Bus) -> u8 {
+    fn cb_read_target<M: MemoryInterface>(&self, z: u8, bus: &mut M) -> u8 {
         match z {
             0 => self.regs.b,
             1 => self.regs.c,
@@ -1038,13 +1737,13 @@ impl Cpu {
             3 => self.regs.e,
             4 => self.regs.h,
             5 => self.regs.l,
-            6 => bus.read8(self.regs.get_hl()),
+            6 => read(bus, self.regs.get_hl()),
             7 => self.regs.a,
             _ => unreachable!(),
         }
     }
 
-    fn cb_write_target(&mut self, z: u8, bus: &mut Bus, result: u8) {
+    fn cb_write_target<M: MemoryInterface>(&mut self, z: u8, bus: &mut M, result: u8) {
         match z {
             0 => self.regs.b = result,
             1 => self.regs.c = result,
@@ -1052,13 +1751,13 @@ impl Cpu {
             3 => self.regs.e = result,
             4 => self.regs.h = result,
             5 => self.regs.l = result,
-            6 => bus.write8(self.regs.get_hl(), result),
+            6 => write(bus, self.regs.get_hl(), result),
             7 => self.regs.a = result,
             _ => unreachable!(),
         }
     }
 
-    fn cb_bit(&mut self, y: u8, z: u8, bus: &mut Bus) -> u8 {
+    fn cb_bit<M: MemoryInterface>(&mut self, y: u8, z: u8, bus: &mut M) -> u8 {
         let value = self.cb_read_target(z, bus);
         let mask = 1 << y;
 
@@ -1070,7 +1769,7 @@ impl Cpu {
         if z == 6 { 12 } else { 8 }
     }
 
-    fn cb_res(&mut self, y: u8, z: u8, bus: &mut Bus) -> u8 {
+    fn cb_res<M: MemoryInterface>(&mut self, y: u8, z: u8, bus: &mut M) -> u8 {
         let value = self.cb_read_target(z, bus);
         let result = value & !(1 << y);
 
@@ -1080,7 +1779,7 @@ impl Cpu {
         if z == 6 { 16 } else { 8 }
     }
 
-    fn cb_set(&mut self, y: u8, z: u8, bus: &mut Bus) -> u8 {
+    fn cb_set<M: MemoryInterface>(&mut self, y: u8, z: u8, bus: &mut M) -> u8 {
         let value = self.cb_read_target(z, bus);
         let result = value | (1 << y);
 
@@ -1,22 +1,19 @@
-use core::panic;
-
 #[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Instruction {
     NOP,
     LDBCD16, // LD BC, d16
     LDBCA,   // LD (BC), A
     INCBC,   // INC BC
-    DECB,
+    INC(Operand8),
+    DEC(Operand8),
     LDDED16,
     INCDE,
     JRNZR8, // JR NZ, r8
     INCHL,  // INC HL
-    INCH,
-    JRZR8, // JR Z, r8
+    JRZR8,  // JR Z, r8
     LDADE,
     LDAHLINC, // LD A, (HL+)
-    INCL,
-    DECL,
     ADD(ArithmeticTarget),
     LDIMM8(Register8),
     LDA8A,     // LD (a8), A
@@ -63,6 +60,73 @@ pub enum Instruction {
     POPAF,
     PUSHAF,
     CPD8,
+
+    // 16-bit loads and arithmetic
+    LDA16SP,  // LD (a16), SP
+    LDSPHL,   // LD SP, HL
+    LDHLSPR8, // LD HL, SP+r8
+    ADDSPR8,  // ADD SP, r8
+    INCSP,
+    DECBC,
+    DECDE,
+    DECHL,
+    DECSP,
+    ADDHLBC, // ADD HL, BC
+    ADDHLDE,
+    ADDHLHL,
+    ADDHLSP,
+    POPDE,
+
+    // remaining 8-bit loads
+    LDABC,    // LD A, (BC)
+    LDDEA,    // LD (DE), A
+    LDAHLDEC, // LD A, (HL-)
+    LDHLD8,   // LD (HL), d8
+    LDCA,     // LD (C), A
+    LDAC,     // LD A, (C)
+
+    // 8-bit ALU
+    ADDAHL, // ADD A, (HL)
+    ADC(Operand8),
+    SUB(Operand8),
+    SBC(Operand8),
+    AND(Operand8),
+    ADCD8,
+    SBCD8,
+    XORD8,
+    ORD8,
+
+    // rotates on A and misc flag ops
+    RLCA,
+    RRCA,
+    RLA,
+    RRA,
+    DAA,
+    CPL,
+    SCF,
+    CCF,
+
+    // control flow
+    JRNCR8, // JR NC, r8
+    JRCR8,  // JR C, r8
+    JPNZA16,
+    JPZA16,
+    JPNCA16,
+    JPCA16,
+    JPHL, // JP HL
+    CALLZA16,
+    CALLNCA16,
+    CALLCA16,
+    RETNZ,
+    RETZ,
+    RETNC,
+    RETC,
+    RETI,
+    RST(u16), // target vector
+    EI,
+    HALT,
+    STOP,
+    ILLEGAL(u8), // opcode
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -99,21 +163,55 @@ pub fn decode(opcode: u8) -> Instruction {
         0x01 => Instruction::LDBCD16,
         0x02 => Instruction::LDBCA,
         0x03 => Instruction::INCBC,
-        0x05 => Instruction::DECB,
+        0x07 => Instruction::RLCA,
+        0x08 => Instruction::LDA16SP,
+        0x09 => Instruction::ADDHLBC,
+        0x0A => Instruction::LDABC,
+        0x0B => Instruction::DECBC,
+        0x0F => Instruction::RRCA,
 
+        0x10 => Instruction::STOP,
         0x11 => Instruction::LDDED16,
+        0x12 => Instruction::LDDEA,
         0x13 => Instruction::INCDE,
+        0x17 => Instruction::RLA,
+        0x19 => Instruction::ADDHLDE,
         0x1A => Instruction::LDADE,
+        0x1B => Instruction::DECDE,
+        0x1F => Instruction::RRA,
 
         0x20 => Instruction::JRNZR8,
         0x21 => Instruction::LDHLD16,
         0x22 => Instruction::LDHLPOSA,
         0x23 => Instruction::INCHL,
-        0x24 => Instruction::INCH,
+        0x27 => Instruction::DAA,
         0x28 => Instruction::JRZR8,
+        0x29 => Instruction::ADDHLHL,
         0x2A => Instruction::LDAHLINC,
-        0x2C => Instruction::INCL,
-        0x2D => Instruction::DECL,
+        0x2B => Instruction::DECHL,
+        0x2F => Instruction::CPL,
+
+        0x30 => Instruction::JRNCR8,
+        0x33 => Instruction::INCSP,
+        0x36 => Instruction::LDHLD8,
+        0x37 => Instruction::SCF,
+        0x38 => Instruction::JRCR8,
+        0x39 => Instruction::ADDHLSP,
+        0x3A => Instruction::LDAHLDEC,
+        0x3B => Instruction::DECSP,
+        0x3F => Instruction::CCF,
+
+        // INC r (0x04, 0x0C, ..., 0x3C)
+        0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x34 | 0x3C => {
+            let reg = decode_reg((opcode >> 3) & 0b111);
+            Instruction::INC(reg)
+        }
+
+        // DEC r (0x05, 0x0D, ..., 0x3D)
+        0x05 | 0x0D | 0x15 | 0x1D | 0x25 | 0x2D | 0x35 | 0x3D => {
+            let reg = decode_reg((opcode >> 3) & 0b111);
+            Instruction::DEC(reg)
+        }
 
         // CP r (0xB8-0xBF)
         0xB8..=0xBF => {
@@ -133,7 +231,7 @@ pub fn decode(opcode: u8) -> Instruction {
         }
 
         // HALT
-        0x76 => panic!("HALT not implemented here"),
+        0x76 => Instruction::HALT,
 
         // ADD A, r
         0x87 => Instruction::ADD(ArithmeticTarget::A),
@@ -143,6 +241,19 @@ pub fn decode(opcode: u8) -> Instruction {
         0x83 => Instruction::ADD(ArithmeticTarget::E),
         0x84 => Instruction::ADD(ArithmeticTarget::H),
         0x85 => Instruction::ADD(ArithmeticTarget::L),
+        0x86 => Instruction::ADDAHL,
+
+        // ADC A, r (0x88-0x8F)
+        0x88..=0x8F => Instruction::ADC(decode_reg(opcode & 0b111)),
+
+        // SUB r (0x90-0x97)
+        0x90..=0x97 => Instruction::SUB(decode_reg(opcode & 0b111)),
+
+        // SBC A, r (0x98-0x9F)
+        0x98..=0x9F => Instruction::SBC(decode_reg(opcode & 0b111)),
+
+        // AND r (0xA0-0xA7)
+        0xA0..=0xA7 => Instruction::AND(decode_reg(opcode & 0b111)),
 
         // LD r, d8
         0x06 => Instruction::LDIMM8(Register8::B),
@@ -183,35 +294,66 @@ pub fn decode(opcode: u8) -> Instruction {
         0xB6 => Instruction::ORHL,
         0xB7 => Instruction::ORA,
 
+        0xC0 => Instruction::RETNZ,
         0xC1 => Instruction::POPBC,
+        0xC2 => Instruction::JPNZA16,
         0xC3 => Instruction::JPA16,
         0xC4 => Instruction::CALLNZA16,
         0xC5 => Instruction::PUSHBC,
         0xC6 => Instruction::ADDAD8,
+        0xC8 => Instruction::RETZ,
 
         0xC9 => Instruction::RET,
+        0xCA => Instruction::JPZA16,
         0xCB => Instruction::PREFIXCB,
+        0xCC => Instruction::CALLZA16,
 
         // CALLA16
         0xCD => Instruction::CALLA16,
+        0xCE => Instruction::ADCD8,
 
+        0xD0 => Instruction::RETNC,
+        0xD1 => Instruction::POPDE,
+        0xD2 => Instruction::JPNCA16,
+        0xD4 => Instruction::CALLNCA16,
         0xD5 => Instruction::PUSHDE,
         0xD6 => Instruction::SUBD8,
+        0xD8 => Instruction::RETC,
+        0xD9 => Instruction::RETI,
+        0xDA => Instruction::JPCA16,
+        0xDC => Instruction::CALLCA16,
+        0xDE => Instruction::SBCD8,
 
         // POP HL
         0xE1 => Instruction::POPHL,
+        0xE2 => Instruction::LDCA,
 
         // PUSH HL
         0xE5 => Instruction::PUSHHL,
         0xE6 => Instruction::ANDD8,
+        0xE8 => Instruction::ADDSPR8,
+        0xE9 => Instruction::JPHL,
+        0xEE => Instruction::XORD8,
 
         0xF0 => Instruction::LDHAA8,
         0xF1 => Instruction::POPAF,
+        0xF2 => Instruction::LDAC,
         0xF3 => Instruction::DI,
         0xF5 => Instruction::PUSHAF,
+        0xF6 => Instruction::ORD8,
+        0xF8 => Instruction::LDHLSPR8,
+        0xF9 => Instruction::LDSPHL,
+        0xFB => Instruction::EI,
         0xFE => Instruction::CPD8,
 
-        _ => panic!("Unimplemented opcode: 0x{:02X}", opcode),
+        // RST n (0xC7, 0xCF, ..., 0xFF)
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
+            Instruction::RST((opcode & 0b0011_1000) as u16)
+        }
+
+        // 0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB-0xED, 0xF4, 0xFC and 0xFD
+        // don't exist on the LR35902 and lock up real hardware
+        _ => Instruction::ILLEGAL(opcode),
     }
 }
 
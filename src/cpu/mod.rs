@@ -1,13 +1,63 @@
 pub mod execute;
 pub mod instructions;
+pub mod recording;
 pub mod registers;
 
+#[cfg(test)]
+mod tests;
+
+use crate::interrupts::Interrupt;
+use crate::model::Model;
 use registers::Registers;
 
+// everything the CPU needs from the outside world. Bus is the real one, but
+// anything implementing this can be stepped, e.g. the RecordingBus
+pub trait MemoryInterface {
+    // takes &mut so implementations can record reads
+    fn read8(&mut self, addr: u16) -> u8;
+    fn write8(&mut self, addr: u16, value: u8);
+
+    // called at the end of every M-cycle of an instruction, including the
+    // ones without a bus access, so everything else on the bus runs in
+    // step with the CPU's own reads and writes
+    fn tick_mcycle(&mut self);
+
+    // interrupts that are both requested and enabled, regardless of IME
+    fn pending_interrupts(&self) -> u8;
+    // clears the IF bit when the CPU jumps to the handler
+    fn acknowledge_interrupt(&mut self, interrupt: Interrupt);
+
+    // held by HDMA or a speed switch
+    fn cpu_stalled(&self) -> bool {
+        false
+    }
+
+    // called by STOP, which resets DIV. Returns true if it started a CGB
+    // speed switch, otherwise DIV is held until `resume`
+    fn stop(&mut self) -> bool {
+        false
+    }
+
+    // a joypad line went low and woke the CPU from STOP
+    fn resume(&mut self) {}
+
+    // P10-P13 as P1 would read them, polled during STOP without a bus access
+    fn joypad_lines(&self) -> u8;
+}
+
 pub struct Cpu {
     pub regs: Registers,
     pub halted: bool,
+    pub stopped: bool,
+    // HALT with IME=0 and an interrupt already pending fails to
+    // increment PC on the next opcode fetch
+    pub halt_bug: bool,
     pub ime: bool, // interrupt master enable
+    // an illegal opcode stops the CPU fetching for good
+    pub locked: bool,
+
+    // EI only enables interrupts after the next instruction
+    pub ime_pending: bool,
 }
 
 impl Cpu {
@@ -15,17 +65,46 @@ impl Cpu {
         Self {
             regs: Registers::default(),
             halted: false,
+            stopped: false,
+            halt_bug: false,
             ime: false,
+            locked: false,
+            ime_pending: false,
         }
     }
 
-    pub fn reset(&mut self) {
+    // registers as the boot ROM leaves them, A tells games which model they're on
+    pub fn reset(&mut self, model: Model) {
         self.regs = Registers {
             pc: 0x0100,
             sp: 0xFFFE,
             ..Default::default()
         };
+        match model {
+            Model::Dmg => {
+                self.regs.set_af(0x01B0);
+                self.regs.set_bc(0x0013);
+                self.regs.set_de(0x00D8);
+                self.regs.set_hl(0x014D);
+            }
+            Model::Cgb => {
+                self.regs.set_af(0x1180);
+                self.regs.set_bc(0x0000);
+                self.regs.set_de(0xFF56);
+                self.regs.set_hl(0x000D);
+            }
+        }
         self.halted = false;
+        self.stopped = false;
+        self.halt_bug = false;
         self.ime = false;
+        self.locked = false;
+        self.ime_pending = false;
+    }
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
     }
 }
@@ -0,0 +1,94 @@
+use super::MemoryInterface;
+use crate::interrupts::Interrupt;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Access {
+    pub cycle: u64, // T-cycle the access happened in, from the start of the recording
+    pub addr: u16,
+    pub value: u8,
+    pub kind: AccessKind,
+}
+
+// flat 64 KiB of memory that logs every access the CPU makes, for checking
+// the order and timing of an instruction's bus traffic. IE and IF are plain
+// bytes at 0xFFFF and 0xFF0F
+pub struct RecordingBus {
+    pub memory: Box<[u8; 0x10000]>,
+    pub accesses: Vec<Access>,
+    pub cycle: u64,
+    pub joypad_lines: u8,
+}
+
+impl RecordingBus {
+    pub fn new() -> Self {
+        Self {
+            memory: Box::new([0; 0x10000]),
+            accesses: Vec::new(),
+            cycle: 0,
+            joypad_lines: 0x0F,
+        }
+    }
+
+    // copies `bytes` in at `addr` without recording anything
+    pub fn load(&mut self, addr: u16, bytes: &[u8]) {
+        let start = addr as usize;
+        self.memory[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+
+    // forgets the accesses so far and starts counting cycles from 0
+    pub fn clear(&mut self) {
+        self.accesses.clear();
+        self.cycle = 0;
+    }
+
+    fn record(&mut self, addr: u16, value: u8, kind: AccessKind) {
+        self.accesses.push(Access {
+            cycle: self.cycle,
+            addr,
+            value,
+            kind,
+        });
+    }
+}
+
+impl MemoryInterface for RecordingBus {
+    fn read8(&mut self, addr: u16) -> u8 {
+        let value = self.memory[addr as usize];
+        self.record(addr, value, AccessKind::Read);
+
+        value
+    }
+
+    fn write8(&mut self, addr: u16, value: u8) {
+        self.memory[addr as usize] = value;
+        self.record(addr, value, AccessKind::Write);
+    }
+
+    fn tick_mcycle(&mut self) {
+        self.cycle += 4;
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.memory[0xFFFF] & self.memory[0xFF0F] & 0x1F
+    }
+
+    fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.memory[0xFF0F] &= !interrupt.mask();
+    }
+
+    fn joypad_lines(&self) -> u8 {
+        self.joypad_lines
+    }
+}
+
+impl Default for RecordingBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,334 @@
+use super::Cpu;
+use super::recording::{Access, AccessKind, RecordingBus};
+
+const PROGRAM: u16 = 0xC000;
+
+// opcodes that lock up the CPU on hardware
+const ILLEGAL: [u8; 11] = [
+    0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+];
+
+// a CPU about to run `program` from WRAM
+fn setup(program: &[u8]) -> (Cpu, RecordingBus) {
+    let mut cpu = Cpu::new();
+    cpu.regs.pc = PROGRAM;
+    cpu.regs.sp = 0xDFF0;
+
+    let mut bus = RecordingBus::new();
+    bus.load(PROGRAM, program);
+
+    (cpu, bus)
+}
+
+fn read(cycle: u64, addr: u16, value: u8) -> Access {
+    Access {
+        cycle,
+        addr,
+        value,
+        kind: AccessKind::Read,
+    }
+}
+
+fn write(cycle: u64, addr: u16, value: u8) -> Access {
+    Access {
+        cycle,
+        addr,
+        value,
+        kind: AccessKind::Write,
+    }
+}
+
+#[test]
+fn call_pushes_the_return_address_high_byte_first() {
+    let (mut cpu, mut bus) = setup(&[0xCD, 0x34, 0x12]); // CALL 0x1234
+
+    assert_eq!(cpu.step(&mut bus), 24);
+    assert_eq!(
+        bus.accesses,
+        [
+            read(0, 0xC000, 0xCD),
+            read(4, 0xC001, 0x34),
+            read(8, 0xC002, 0x12),
+            write(16, 0xDFEF, 0xC0),
+            write(20, 0xDFEE, 0x03),
+        ]
+    );
+    assert_eq!(cpu.regs.pc, 0x1234);
+    assert_eq!(cpu.regs.sp, 0xDFEE);
+}
+
+#[test]
+fn cb_ops_on_hl_read_then_write_back() {
+    let (mut cpu, mut bus) = setup(&[0xCB, 0x06]); // RLC (HL)
+    cpu.regs.set_hl(0xD000);
+    bus.load(0xD000, &[0x85]);
+
+    assert_eq!(cpu.step(&mut bus), 16);
+    assert_eq!(
+        bus.accesses,
+        [
+            read(0, 0xC000, 0xCB),
+            read(4, 0xC001, 0x06),
+            read(8, 0xD000, 0x85),
+            write(12, 0xD000, 0x0B),
+        ]
+    );
+    assert!(cpu.regs.get_c());
+}
+
+#[test]
+fn bit_on_hl_only_reads() {
+    let (mut cpu, mut bus) = setup(&[0xCB, 0x7E]); // BIT 7, (HL)
+    cpu.regs.set_hl(0xD000);
+
+    assert_eq!(cpu.step(&mut bus), 12);
+    assert_eq!(bus.accesses.last(), Some(&read(8, 0xD000, 0x00)));
+    assert!(cpu.regs.get_z());
+}
+
+#[test]
+fn interrupt_dispatch_pushes_pc_and_jumps_to_the_vector() {
+    let (mut cpu, mut bus) = setup(&[0x00]);
+    cpu.ime = true;
+    bus.memory[0xFFFF] = 0x04;
+    bus.memory[0xFF0F] = 0x04; // timer
+
+    assert_eq!(cpu.step(&mut bus), 20);
+    assert_eq!(
+        bus.accesses,
+        [write(8, 0xDFEF, 0xC0), write(12, 0xDFEE, 0x00)]
+    );
+    assert_eq!(bus.cycle, 20);
+    assert_eq!(cpu.regs.pc, 0x0050);
+    assert_eq!(bus.memory[0xFF0F], 0x00);
+    assert!(!cpu.ime);
+}
+
+#[test]
+fn stop_polls_the_joypad_without_bus_reads() {
+    let (mut cpu, mut bus) = setup(&[0x10, 0x00, 0x00]); // STOP, NOP
+
+    cpu.step(&mut bus);
+    bus.clear();
+    for _ in 0..3 {
+        assert_eq!(cpu.step(&mut bus), 4);
+    }
+    assert!(cpu.stopped);
+    assert!(bus.accesses.is_empty());
+    assert_eq!(bus.cycle, 12);
+
+    bus.joypad_lines = 0x0E;
+    cpu.step(&mut bus);
+    assert!(!cpu.stopped);
+    assert_eq!(bus.accesses, [read(12, 0xC002, 0x00)]);
+}
+
+#[test]
+fn illegal_opcodes_lock_up_the_cpu() {
+    for opcode in ILLEGAL {
+        let (mut cpu, mut bus) = setup(&[opcode, 0x00]);
+        cpu.ime = true;
+
+        assert_eq!(cpu.step(&mut bus), 4);
+        assert!(cpu.locked);
+
+        // no more fetches, and interrupts don't get it going again
+        bus.clear();
+        bus.memory[0xFFFF] = 0x01;
+        bus.memory[0xFF0F] = 0x01;
+        for _ in 0..3 {
+            assert_eq!(cpu.step(&mut bus), 4);
+        }
+        assert!(bus.accesses.is_empty(), "0x{opcode:02X}");
+        assert_eq!(bus.cycle, 12);
+        assert_eq!(cpu.regs.pc, PROGRAM + 1);
+    }
+}
+
+// the M-cycles an instruction reports to the bus have to add up to the
+// T-cycles it returns, taken and not taken branches alike
+#[test]
+fn every_instruction_ticks_once_per_m_cycle() {
+    let cb = (0..=0xFF).map(|op| [0xCB, op]);
+    let base = (0..=0xFF).filter(|op| *op != 0xCB).map(|op| [op, 0x00]);
+
+    for program in base.chain(cb) {
+        for flags in [0x00, 0xF0] {
+            let (mut cpu, mut bus) = setup(&program);
+            cpu.regs.f = flags;
+            cpu.regs.set_hl(0xD000);
+
+            let cycles = cpu.step(&mut bus);
+            assert_eq!(
+                bus.cycle, cycles as u64,
+                "{program:02X?} with F=0x{flags:02X}"
+            );
+        }
+    }
+}
+
+// runs `program` to its end and returns the CPU
+fn execute(program: &[u8], init: impl FnOnce(&mut Cpu)) -> Cpu {
+    let (mut cpu, mut bus) = setup(program);
+    init(&mut cpu);
+
+    while cpu.regs.pc < PROGRAM + program.len() as u16 {
+        cpu.step(&mut bus);
+    }
+
+    cpu
+}
+
+// Z N H C
+fn flags(cpu: &Cpu) -> [bool; 4] {
+    let r = &cpu.regs;
+    [r.get_z(), r.get_n(), r.get_h(), r.get_c()]
+}
+
+#[test]
+fn daa_after_add() {
+    // ADD A, B; DAA
+    let cpu = execute(&[0x80, 0x27], |cpu| {
+        cpu.regs.a = 0x45;
+        cpu.regs.b = 0x38;
+    });
+    assert_eq!(cpu.regs.a, 0x83);
+    assert_eq!(flags(&cpu), [false, false, false, false]);
+
+    // a half carry from the low digit
+    let cpu = execute(&[0x80, 0x27], |cpu| {
+        cpu.regs.a = 0x09;
+        cpu.regs.b = 0x08;
+    });
+    assert_eq!(cpu.regs.a, 0x17);
+    assert_eq!(flags(&cpu), [false, false, false, false]);
+
+    // 99 + 1 wraps to 00 with carry
+    let cpu = execute(&[0x80, 0x27], |cpu| {
+        cpu.regs.a = 0x99;
+        cpu.regs.b = 0x01;
+    });
+    assert_eq!(cpu.regs.a, 0x00);
+    assert_eq!(flags(&cpu), [true, false, false, true]);
+}
+
+#[test]
+fn daa_after_sub() {
+    // SUB B; DAA
+    let cpu = execute(&[0x90, 0x27], |cpu| {
+        cpu.regs.a = 0x42;
+        cpu.regs.b = 0x13;
+    });
+    assert_eq!(cpu.regs.a, 0x29);
+    assert_eq!(flags(&cpu), [false, true, false, false]);
+
+    // 10 - 20 borrows and leaves 90
+    let cpu = execute(&[0x90, 0x27], |cpu| {
+        cpu.regs.a = 0x10;
+        cpu.regs.b = 0x20;
+    });
+    assert_eq!(cpu.regs.a, 0x90);
+    assert_eq!(flags(&cpu), [false, true, false, true]);
+}
+
+#[test]
+fn add_sp_flags_come_from_the_low_byte_with_negative_offsets() {
+    // ADD SP, -1
+    let cpu = execute(&[0xE8, 0xFF], |cpu| cpu.regs.sp = 0xDFF8);
+    assert_eq!(cpu.regs.sp, 0xDFF7);
+    assert_eq!(flags(&cpu), [false, false, true, true]);
+
+    let cpu = execute(&[0xE8, 0xFF], |cpu| cpu.regs.sp = 0xD000);
+    assert_eq!(cpu.regs.sp, 0xCFFF);
+    assert_eq!(flags(&cpu), [false, false, false, false]);
+
+    // Z is always cleared
+    let cpu = execute(&[0xE8, 0x80], |cpu| {
+        cpu.regs.sp = 0x0080;
+        cpu.regs.f = 0x80;
+    });
+    assert_eq!(cpu.regs.sp, 0x0000);
+    assert_eq!(flags(&cpu), [false, false, false, true]);
+}
+
+#[test]
+fn ld_hl_sp_offset_flags_with_negative_offsets() {
+    // LD HL, SP-2
+    let cpu = execute(&[0xF8, 0xFE], |cpu| cpu.regs.sp = 0xDFF8);
+    assert_eq!(cpu.regs.get_hl(), 0xDFF6);
+    assert_eq!(cpu.regs.sp, 0xDFF8);
+    assert_eq!(flags(&cpu), [false, false, true, true]);
+
+    let cpu = execute(&[0xF8, 0xFE], |cpu| cpu.regs.sp = 0xD001);
+    assert_eq!(cpu.regs.get_hl(), 0xCFFF);
+    assert_eq!(flags(&cpu), [false, false, false, false]);
+}
+
+#[test]
+fn adc_carry_in_counts_towards_half_carry() {
+    // ADC A, B
+    let cpu = execute(&[0x88], |cpu| {
+        cpu.regs.a = 0x0F;
+        cpu.regs.b = 0x00;
+        cpu.regs.set_c(true);
+    });
+    assert_eq!(cpu.regs.a, 0x10);
+    assert_eq!(flags(&cpu), [false, false, true, false]);
+
+    let cpu = execute(&[0x88], |cpu| {
+        cpu.regs.a = 0xFF;
+        cpu.regs.b = 0x00;
+        cpu.regs.set_c(true);
+    });
+    assert_eq!(cpu.regs.a, 0x00);
+    assert_eq!(flags(&cpu), [true, false, true, true]);
+}
+
+#[test]
+fn sbc_carry_in_counts_towards_half_borrow() {
+    // SBC A, B
+    let cpu = execute(&[0x98], |cpu| {
+        cpu.regs.a = 0x10;
+        cpu.regs.b = 0x00;
+        cpu.regs.set_c(true);
+    });
+    assert_eq!(cpu.regs.a, 0x0F);
+    assert_eq!(flags(&cpu), [false, true, true, false]);
+
+    let cpu = execute(&[0x98], |cpu| {
+        cpu.regs.a = 0x00;
+        cpu.regs.b = 0x00;
+        cpu.regs.set_c(true);
+    });
+    assert_eq!(cpu.regs.a, 0xFF);
+    assert_eq!(flags(&cpu), [false, true, true, true]);
+}
+
+#[test]
+fn conditional_branches_take_longer_when_taken() {
+    // opcode, F that takes the branch, F that doesn't, cycles taken/not taken
+    let branches = [
+        (0x20, 0x00, 0x80, 12, 8),  // JR NZ
+        (0x38, 0x10, 0x00, 12, 8),  // JR C
+        (0xCA, 0x80, 0x00, 16, 12), // JP Z
+        (0xD2, 0x00, 0x10, 16, 12), // JP NC
+        (0xC4, 0x00, 0x80, 24, 12), // CALL NZ
+        (0xDC, 0x10, 0x00, 24, 12), // CALL C
+        (0xC8, 0x80, 0x00, 20, 8),  // RET Z
+        (0xD0, 0x00, 0x10, 20, 8),  // RET NC
+    ];
+
+    for (opcode, taken, not_taken, taken_cycles, not_taken_cycles) in branches {
+        let (mut cpu, mut bus) = setup(&[opcode, 0x00, 0x00]);
+        cpu.regs.f = taken;
+        assert_eq!(cpu.step(&mut bus), taken_cycles, "0x{opcode:02X} taken");
+
+        let (mut cpu, mut bus) = setup(&[opcode, 0x00, 0x00]);
+        cpu.regs.f = not_taken;
+        assert_eq!(
+            cpu.step(&mut bus),
+            not_taken_cycles,
+            "0x{opcode:02X} not taken"
+        );
+    }
+}
@@ -0,0 +1,361 @@
+// OAM DMA, started by writing the source page to 0xFF46. Copies 160 bytes
+// to OAM, one per M-cycle
+pub struct OamDma {
+    register: u8,
+    source: u16,
+    // next byte to copy, None when no transfer is running
+    index: Option<u8>,
+    // a write takes an M-cycle to start, a transfer that's already
+    // running carries on until then
+    restart: Option<u16>,
+}
+
+impl OamDma {
+    pub fn new() -> Self {
+        Self {
+            register: 0xFF,
+            source: 0,
+            index: None,
+            restart: None,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        self.register
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.register = value;
+        self.restart = Some((value as u16) << 8);
+    }
+
+    // the CPU is locked out of OAM and the source's bus while this is true
+    pub fn active(&self) -> bool {
+        self.index.is_some()
+    }
+
+    pub fn source(&self) -> u16 {
+        self.source
+    }
+
+    // advances one M-cycle, returns the address to copy from and the OAM
+    // offset to copy to if a byte is due this cycle
+    pub fn step(&mut self) -> Option<(u16, u8)> {
+        let copy = self.index.map(|index| (self.source + index as u16, index));
+        self.index = self
+            .index
+            .map(|index| index + 1)
+            .filter(|&index| index < 0xA0);
+
+        if let Some(source) = self.restart.take() {
+            self.source = source;
+            self.index = Some(0);
+        }
+
+        copy
+    }
+}
+
+impl Default for OamDma {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// CGB VRAM DMA through 0xFF51-0xFF55. General purpose DMA copies everything
+// at once, HBlank DMA copies 16 bytes at the start of each HBlank. Either
+// way the CPU is stopped while bytes are being moved
+pub struct Hdma {
+    source: u16,
+    dest: u16, // offset into VRAM
+    // HDMA5 as it reads back: blocks left minus one in bits 0-6, bit 7
+    // clear while an HBlank transfer is running
+    hdma5: u8,
+    hblank_mode: bool,
+    // bytes left in the burst being copied right now
+    burst: u16,
+    // T-cycles towards the next byte
+    cycles: u8,
+}
+
+impl Hdma {
+    pub fn new() -> Self {
+        Self {
+            source: 0,
+            dest: 0,
+            hdma5: 0xFF,
+            hblank_mode: false,
+            burst: 0,
+            cycles: 0,
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            // the address registers are write only
+            0xFF51..=0xFF54 => 0xFF,
+            0xFF55 => self.hdma5,
+            _ => unreachable!("Not an HDMA register: 0x{:04X}", addr),
+        }
+    }
+
+    // `in_hblank`: an HBlank transfer started during HBlank copies its
+    // first block straight away
+    pub fn write(&mut self, addr: u16, value: u8, in_hblank: bool) {
+        match addr {
+            0xFF51 => self.source = (self.source & 0x00F0) | (value as u16) << 8,
+            0xFF52 => self.source = (self.source & 0xFF00) | (value & 0xF0) as u16,
+            0xFF53 => self.dest = (self.dest & 0x00F0) | ((value & 0x1F) as u16) << 8,
+            0xFF54 => self.dest = (self.dest & 0x1F00) | (value & 0xF0) as u16,
+            0xFF55 => {
+                // bit 7 clear while an HBlank transfer runs cancels it
+                if self.hblank_active() && value & 0x80 == 0 {
+                    self.hdma5 |= 0x80;
+                    return;
+                }
+
+                self.hdma5 = value & 0x7F;
+                self.hblank_mode = value & 0x80 != 0;
+
+                if !self.hblank_mode {
+                    self.burst = (self.hdma5 as u16 + 1) * 0x10;
+                } else if in_hblank {
+                    self.start_hblank_block();
+                }
+            }
+            _ => unreachable!("Not an HDMA register: 0x{:04X}", addr),
+        }
+    }
+
+    fn hblank_active(&self) -> bool {
+        self.hblank_mode && self.hdma5 & 0x80 == 0
+    }
+
+    // called when the PPU enters HBlank on a visible line
+    pub fn start_hblank_block(&mut self) {
+        if self.hblank_active() && self.burst == 0 {
+            self.burst = 0x10;
+        }
+    }
+
+    // the CPU doesn't run while a burst is being copied
+    pub fn stalls_cpu(&self) -> bool {
+        self.burst > 0
+    }
+
+    // advances one T-cycle of real time, returns the source address and
+    // VRAM offset of a byte due to be copied. That's one byte every 2
+    // cycles, so 16 bytes take 8 M-cycles in normal speed
+    pub fn step(&mut self) -> Option<(u16, u16)> {
+        if self.burst == 0 {
+            return None;
+        }
+
+        self.cycles += 1;
+        if self.cycles < 2 {
+            return None;
+        }
+        self.cycles = 0;
+
+        let copy = (self.source, self.dest);
+        self.source = self.source.wrapping_add(1);
+        self.dest = (self.dest + 1) & 0x1FFF;
+        self.burst -= 1;
+
+        // the length counts down once per 16 byte block, wrapping to 0xFF
+        // when the last one is done
+        if self.dest & 0x0F == 0 {
+            self.hdma5 = self.hdma5.wrapping_sub(1);
+            if self.hdma5 == 0xFF {
+                self.burst = 0;
+            }
+        }
+
+        Some(copy)
+    }
+}
+
+impl Default for Hdma {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn oam_dma_starts_one_m_cycle_after_the_write() {
+        let mut dma = OamDma::new();
+        dma.write(0xC1);
+        assert!(!dma.active());
+
+        assert_eq!(dma.step(), None);
+        assert!(dma.active());
+        assert_eq!(dma.step(), Some((0xC100, 0x00)));
+    }
+
+    #[test]
+    fn oam_dma_copies_160_bytes() {
+        let mut dma = OamDma::new();
+        dma.write(0xC1);
+        dma.step();
+
+        let copies: Vec<_> = std::iter::from_fn(|| dma.step()).collect();
+        assert_eq!(copies.len(), 0xA0);
+        assert_eq!(copies[0x9F], (0xC19F, 0x9F));
+        assert!(!dma.active());
+        assert_eq!(dma.read(), 0xC1);
+    }
+
+    #[test]
+    fn oam_dma_restarted_mid_transfer_carries_on_for_one_m_cycle() {
+        let mut dma = OamDma::new();
+        dma.write(0xC1);
+        for _ in 0..11 {
+            dma.step();
+        }
+
+        dma.write(0xD2);
+        assert_eq!(dma.step(), Some((0xC10A, 0x0A)));
+        assert_eq!(dma.step(), Some((0xD200, 0x00)));
+        assert!(dma.active());
+    }
+
+    // a DMG bus with OAM DMA from 0xC000 two bytes in
+    fn bus_mid_oam_dma() -> Bus {
+        let mut bus = Bus::new();
+        bus.write8(0xC000, 0x11);
+        bus.write8(0xC001, 0x22);
+        bus.write8(0xD000, 0x33);
+        bus.write8(0x8000, 0x44);
+        bus.write8(0xFF80, 0x55);
+
+        bus.write8(0xFF46, 0xC0);
+        for _ in 0..3 {
+            bus.tick(4);
+        }
+
+        bus
+    }
+
+    #[test]
+    fn oam_dma_blocks_oam_and_conflicts_on_the_source_bus() {
+        let mut bus = bus_mid_oam_dma();
+
+        assert_eq!(bus.read8(0xFE00), 0xFF);
+        // WRAM shares the external bus with the source, the CPU sees the
+        // byte DMA just read instead
+        assert_eq!(bus.read8(0xD000), 0x22);
+        bus.write8(0xD000, 0x99);
+        // VRAM and HRAM are on buses of their own
+        assert_eq!(bus.read8(0x8000), 0x44);
+        assert_eq!(bus.read8(0xFF80), 0x55);
+
+        for _ in 0..0xA0 {
+            bus.tick(4);
+        }
+        assert_eq!(bus.read8(0xFE00), 0x11);
+        assert_eq!(bus.read8(0xFE01), 0x22);
+        assert_eq!(bus.read8(0xD000), 0x33);
+    }
+
+    #[test]
+    fn oam_dma_from_vram_conflicts_on_vram_only() {
+        let mut bus = Bus::new();
+        bus.write8(0x8000, 0x44);
+        bus.write8(0xC000, 0x11);
+
+        bus.write8(0xFF46, 0x80);
+        bus.tick(4);
+        bus.tick(4);
+
+        assert_eq!(bus.read8(0x9000), 0x44);
+        assert_eq!(bus.read8(0xC000), 0x11);
+    }
+
+    // source 0xC000, destination 0x8000 (VRAM offset 0)
+    fn hdma() -> Hdma {
+        let mut hdma = Hdma::new();
+        for (addr, value) in [
+            (0xFF51, 0xC0),
+            (0xFF52, 0x00),
+            (0xFF53, 0x80),
+            (0xFF54, 0x00),
+        ] {
+            hdma.write(addr, value, false);
+        }
+
+        hdma
+    }
+
+    // steps until the CPU is free again, returns the copies and T-cycles taken
+    fn run_burst(hdma: &mut Hdma) -> (Vec<(u16, u16)>, usize) {
+        let mut copies = Vec::new();
+        let mut cycles = 0;
+        while hdma.stalls_cpu() {
+            copies.extend(hdma.step());
+            cycles += 1;
+        }
+
+        (copies, cycles)
+    }
+
+    #[test]
+    fn general_purpose_dma_copies_everything_at_once() {
+        let mut hdma = hdma();
+        hdma.write(0xFF55, 0x01, false);
+
+        let (copies, cycles) = run_burst(&mut hdma);
+        assert_eq!(copies.len(), 0x20);
+        assert_eq!(cycles, 0x40);
+        assert_eq!(copies[0], (0xC000, 0x0000));
+        assert_eq!(copies[0x1F], (0xC01F, 0x001F));
+        assert_eq!(hdma.read(0xFF55), 0xFF);
+    }
+
+    #[test]
+    fn hblank_dma_counts_down_one_block_per_hblank() {
+        let mut hdma = hdma();
+        hdma.write(0xFF55, 0x82, false);
+        assert!(!hdma.stalls_cpu());
+        assert_eq!(hdma.read(0xFF55), 0x02);
+
+        for remaining in [0x01, 0x00, 0xFF] {
+            hdma.start_hblank_block();
+            let (copies, _) = run_burst(&mut hdma);
+            assert_eq!(copies.len(), 0x10);
+            assert_eq!(hdma.read(0xFF55), remaining);
+        }
+
+        // done, later HBlanks copy nothing
+        hdma.start_hblank_block();
+        assert!(!hdma.stalls_cpu());
+    }
+
+    #[test]
+    fn clearing_bit_7_cancels_an_hblank_transfer() {
+        let mut hdma = hdma();
+        hdma.write(0xFF55, 0x82, false);
+        hdma.start_hblank_block();
+        run_burst(&mut hdma);
+
+        hdma.write(0xFF55, 0x00, false);
+        assert_eq!(hdma.read(0xFF55), 0x81);
+
+        hdma.start_hblank_block();
+        assert!(!hdma.stalls_cpu());
+    }
+
+    #[test]
+    fn hblank_transfer_started_in_hblank_copies_straight_away() {
+        let mut hdma = hdma();
+        hdma.write(0xFF55, 0x80, true);
+
+        let (copies, _) = run_burst(&mut hdma);
+        assert_eq!(copies.len(), 0x10);
+        assert_eq!(hdma.read(0xFF55), 0xFF);
+    }
+}
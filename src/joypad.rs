@@ -0,0 +1,202 @@
+use crate::bus::{Clock, MemoryMappedDevice};
+use crate::interrupts::Interrupt;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    // P10-P13 line the button pulls low
+    fn line(self) -> u8 {
+        match self {
+            Button::Right | Button::A => 0x01,
+            Button::Left | Button::B => 0x02,
+            Button::Up | Button::Select => 0x04,
+            Button::Down | Button::Start => 0x08,
+        }
+    }
+
+    fn is_direction(self) -> bool {
+        matches!(
+            self,
+            Button::Right | Button::Left | Button::Up | Button::Down
+        )
+    }
+}
+
+// P1 (0xFF00). The buttons sit in a 2x4 matrix, P14 selects the d-pad and
+// P15 the action buttons. Everything is active low
+pub struct Joypad {
+    select: u8,     // bits 4-5 as last written
+    directions: u8, // pressed d-pad lines, 1 = pressed
+    actions: u8,    // pressed A/B/Select/Start lines, 1 = pressed
+    interrupt: bool,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self {
+            select: 0x30,
+            directions: 0,
+            actions: 0,
+            interrupt: false,
+        }
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        let before = self.lines();
+
+        let state = if button.is_direction() {
+            &mut self.directions
+        } else {
+            &mut self.actions
+        };
+        if pressed {
+            *state |= button.line();
+        } else {
+            *state &= !button.line();
+        }
+
+        self.check_interrupt(before);
+    }
+
+    // current P10-P13 levels, 0 = low
+    pub fn lines(&self) -> u8 {
+        let mut pressed = 0;
+        if self.select & 0x10 == 0 {
+            pressed |= self.directions;
+        }
+        if self.select & 0x20 == 0 {
+            pressed |= self.actions;
+        }
+
+        !pressed & 0x0F
+    }
+
+    // the interrupt fires when any line goes from high to low
+    fn check_interrupt(&mut self, before: u8) {
+        if before & !self.lines() != 0 {
+            self.interrupt = true;
+        }
+    }
+
+    // returns and clears a pending joypad interrupt request
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.interrupt)
+    }
+}
+
+// P1 at 0xFF00 is the only register
+impl MemoryMappedDevice for Joypad {
+    fn read(&self, _addr: u16) -> u8 {
+        // bits 6-7 are unused and read as 1
+        0xC0 | self.select | self.lines()
+    }
+
+    fn write(&mut self, _addr: u16, value: u8) {
+        let before = self.lines();
+
+        // only the select bits are writable
+        self.select = value & 0x30;
+
+        self.check_interrupt(before);
+    }
+
+    fn tick(&mut self, _clock: &Clock) -> u8 {
+        if self.take_interrupt() {
+            Interrupt::Joypad.mask()
+        } else {
+            0
+        }
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn joypad(pressed: &[Button]) -> Joypad {
+        let mut joypad = Joypad::new();
+        for &button in pressed {
+            joypad.set_button(button, true);
+        }
+        joypad.take_interrupt();
+        joypad
+    }
+
+    #[test]
+    fn p1_reads_the_selected_group() {
+        let mut joypad = joypad(&[Button::Right, Button::Start]);
+
+        // P14 low, d-pad
+        joypad.write(0xFF00, 0x20);
+        assert_eq!(joypad.read(0xFF00), 0xEE);
+
+        // P15 low, action buttons
+        joypad.write(0xFF00, 0x10);
+        assert_eq!(joypad.read(0xFF00), 0xD7);
+
+        // both groups are ANDed onto the same lines
+        joypad.write(0xFF00, 0x00);
+        assert_eq!(joypad.read(0xFF00), 0xC6);
+
+        // with neither selected nothing reads as pressed
+        joypad.write(0xFF00, 0x30);
+        assert_eq!(joypad.read(0xFF00), 0xFF);
+    }
+
+    #[test]
+    fn only_the_select_bits_are_writable() {
+        let mut joypad = joypad(&[]);
+
+        joypad.write(0xFF00, 0x00);
+        assert_eq!(joypad.read(0xFF00), 0xCF);
+        joypad.write(0xFF00, 0xFF);
+        assert_eq!(joypad.read(0xFF00), 0xFF);
+    }
+
+    #[test]
+    fn interrupt_fires_when_a_selected_line_goes_low() {
+        let mut joypad = joypad(&[]);
+        joypad.write(0xFF00, 0x10); // action buttons
+
+        // not selected, the line stays high
+        joypad.set_button(Button::Left, true);
+        assert!(!joypad.take_interrupt());
+
+        joypad.set_button(Button::A, true);
+        assert!(joypad.take_interrupt());
+
+        // releasing is a low to high edge
+        joypad.set_button(Button::A, false);
+        assert!(!joypad.take_interrupt());
+    }
+
+    #[test]
+    fn selecting_a_group_with_a_button_held_fires_the_interrupt() {
+        let mut joypad = joypad(&[Button::Down]);
+        joypad.write(0xFF00, 0x30);
+        assert!(!joypad.take_interrupt());
+
+        joypad.write(0xFF00, 0x20);
+        assert!(joypad.take_interrupt());
+
+        // the line is already low, switching to both groups is no new edge
+        joypad.write(0xFF00, 0x00);
+        assert!(!joypad.take_interrupt());
+    }
+}
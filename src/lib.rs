@@ -0,0 +1,12 @@
+pub mod apu;
+pub mod bus;
+pub mod cartridge;
+pub mod cpu;
+pub mod dma;
+pub mod interrupts;
+pub mod joypad;
+pub mod mmu;
+pub mod model;
+pub mod ppu;
+pub mod serial;
+pub mod timer;
@@ -1,32 +1,185 @@
+use std::env;
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
 
-mod bus;
-mod cpu;
+use gb_emulator::apu::Apu;
+use gb_emulator::apu::wav::WavSink;
+use gb_emulator::bus::Bus;
+use gb_emulator::cartridge::Cartridge;
+use gb_emulator::cartridge::header::CgbSupport;
+use gb_emulator::cartridge::save::BatterySave;
+use gb_emulator::cpu::Cpu;
+use gb_emulator::model::Model;
 
-use bus::Bus;
-use cpu::Cpu;
+// how often battery RAM gets written back while running, unless
+// --autosave says otherwise
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(10);
 
-fn load_rom(bus: &mut Bus, path: &str) {
-    let rom = fs::read(path).expect("Failed to read rom...");
-    for (i, byte) in rom.iter().enumerate() {
-        bus.memory[i] = *byte;
+// T-cycles in one frame, used to pace housekeeping in the main loop
+const CYCLES_PER_FRAME: u64 = 70224;
+
+const DEFAULT_ROM: &str = "roms/cpu_instrs/cpu_instrs.gb";
+
+const USAGE: &str =
+    "usage: gb-emulator [rom] [--wav <file>] [--wav-channels] [--autosave <seconds>]";
+
+struct Options {
+    rom: String,
+    // dump the audio to this .wav file
+    wav: Option<PathBuf>,
+    // plus one file per channel next to it
+    wav_channels: bool,
+    autosave_interval: Duration,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        rom: DEFAULT_ROM.to_string(),
+        wav: None,
+        wav_channels: false,
+        autosave_interval: AUTOSAVE_INTERVAL,
+    };
+    let mut rom = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--wav" => {
+                let path = args.next().ok_or("--wav needs a file name")?;
+                options.wav = Some(PathBuf::from(path));
+            }
+            "--wav-channels" => options.wav_channels = true,
+            "--autosave" => {
+                let seconds = args.next().ok_or("--autosave needs a number of seconds")?;
+                let seconds = seconds
+                    .parse()
+                    .map_err(|_| format!("invalid autosave interval {seconds}"))?;
+                options.autosave_interval = Duration::from_secs(seconds);
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option {arg}")),
+            _ if rom.is_none() => rom = Some(arg),
+            _ => return Err(format!("unexpected argument {arg}")),
+        }
+    }
+
+    if options.wav_channels && options.wav.is_none() {
+        return Err("--wav-channels needs --wav".to_string());
+    }
+    if let Some(rom) = rom {
+        options.rom = rom;
+    }
+
+    Ok(options)
+}
+
+fn load_rom(path: &str, autosave_interval: Duration) -> (Cartridge, Option<BatterySave>) {
+    let rom = fs::read(path).unwrap_or_else(|e| {
+        eprintln!("Failed to read {path}: {e}");
+        process::exit(1);
+    });
+    let mut cartridge = Cartridge::from_bytes(rom).unwrap_or_else(|e| {
+        eprintln!("Failed to load {path}: {e}");
+        process::exit(1);
+    });
+    cartridge.set_rumble_callback(|on| eprintln!("rumble: {}", if on { "on" } else { "off" }));
+
+    println!("{}", cartridge.header);
+    if !cartridge.header.header_checksum_valid(cartridge.rom()) {
+        println!("WARNING: header checksum mismatch, real hardware would refuse to boot");
+    }
+    if !cartridge.header.global_checksum_valid(cartridge.rom()) {
+        println!("WARNING: global checksum mismatch");
+    }
+
+    let save = cartridge.header.has_battery().then(|| {
+        let mut save = BatterySave::new(Path::new(path), autosave_interval);
+        if let Err(e) = save.load(&mut cartridge) {
+            eprintln!("Failed to load {}: {e}", save.path().display());
+        }
+        save
+    });
+
+    (cartridge, save)
+}
+
+fn flush_save(save: &mut Option<BatterySave>, bus: &mut Bus, autosave: bool) {
+    let (Some(save), Some(cartridge)) = (save.as_mut(), bus.device_mut::<Cartridge>()) else {
+        return;
+    };
+
+    let result = if autosave {
+        save.autosave(cartridge)
+    } else {
+        save.flush_on_exit(cartridge)
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to write {}: {e}", save.path().display());
+    }
+}
+
+// with no sink the samples are thrown away so they don't pile up
+fn write_audio(wav: &mut Option<WavSink>, bus: &mut Bus) {
+    let Some(apu) = bus.device_mut::<Apu>() else {
+        return;
+    };
+
+    match wav {
+        Some(sink) => {
+            if let Err(e) = apu.write_to(sink) {
+                eprintln!("Failed to write audio: {e}");
+            }
+        }
+        None => {
+            apu.take_samples();
+        }
     }
 }
 
 fn main() {
+    let options = parse_args().unwrap_or_else(|e| {
+        eprintln!("{e}\n{USAGE}");
+        process::exit(1);
+    });
+
     println!("GameBoy emulator loading...");
 
+    let (cartridge, mut save) = load_rom(&options.rom, options.autosave_interval);
+
+    // anything that knows about the CGB gets run as one
+    let model = match cartridge.header.cgb {
+        CgbSupport::None => Model::Dmg,
+        CgbSupport::Enhanced | CgbSupport::Only => Model::Cgb,
+    };
+
     let mut cpu = Cpu::new();
-    let mut bus = Bus::new();
+    let mut bus = Bus::with_model(model);
+    bus.load_cartridge(cartridge);
+    cpu.reset(model);
 
-    load_rom(&mut bus, "roms/cpu_instrs/cpu_instrs.gb");
-    cpu.reset();
+    let mut wav = options.wav.as_ref().map(|path| {
+        let apu = bus.device_mut::<Apu>().expect("the APU is always attached");
+        apu.set_channel_capture(options.wav_channels);
+        WavSink::create(path, apu.sample_rate(), options.wav_channels).unwrap_or_else(|e| {
+            eprintln!("Failed to create {}: {e}", path.display());
+            process::exit(1);
+        })
+    });
 
     let mut cycles = 0;
+    let mut next_frame = CYCLES_PER_FRAME;
 
     loop {
-        let step_cycles = cpu.step(&mut bus);
-        cycles += step_cycles as u64;
+        // the bus runs alongside each M-cycle of the step
+        cycles += cpu.step(&mut bus) as u64;
+
+        if cycles >= next_frame {
+            next_frame += CYCLES_PER_FRAME;
+            flush_save(&mut save, &mut bus, true);
+
+            write_audio(&mut wav, &mut bus);
+        }
 
         // TEMP: break if emulator locks up
         if cycles > 50_000_000 {
@@ -34,4 +187,13 @@ fn main() {
             break;
         }
     }
+
+    flush_save(&mut save, &mut bus, false);
+
+    write_audio(&mut wav, &mut bus);
+    if let Some(wav) = wav
+        && let Err(e) = wav.finish()
+    {
+        eprintln!("Failed to finish the WAV dump: {e}");
+    }
 }
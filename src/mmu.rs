@@ -0,0 +1,80 @@
+use crate::bus::MemoryMappedDevice;
+use crate::model::Model;
+
+// the console's own RAM behind the bus: WRAM with its echo, HRAM and the
+// unusable region after OAM
+pub struct Mmu {
+    model: Model,
+    // 8 banks of 4 KiB, the DMG only has the first two
+    wram: Box<[u8; 0x8000]>,
+    svbk: u8, // 0xFF70, CGB only
+    hram: [u8; 0x7F],
+}
+
+impl Mmu {
+    pub fn new() -> Self {
+        Self::with_model(Model::Dmg)
+    }
+
+    pub fn with_model(model: Model) -> Self {
+        Self {
+            model,
+            wram: Box::new([0; 0x8000]),
+            svbk: 0,
+            hram: [0; 0x7F],
+        }
+    }
+
+    // 0xC000-0xCFFF is always bank 0, 0xD000-0xDFFF is bank 1 or whatever
+    // SVBK picks. Echo RAM at 0xE000-0xFDFF shows the same bytes
+    fn wram_index(&self, addr: u16) -> usize {
+        let offset = (addr as usize - 0xC000) & 0x1FFF;
+
+        if offset < 0x1000 {
+            offset
+        } else {
+            // bank 0 can't be selected, it maps bank 1
+            let bank = (self.svbk as usize & 0x07).max(1);
+            bank * 0x1000 + (offset - 0x1000)
+        }
+    }
+}
+
+impl MemoryMappedDevice for Mmu {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xC000..=0xFDFF => self.wram[self.wram_index(addr)],
+
+            // what's here depends on the model and revision, the DMG reads 0
+            // and the CGB repeats the upper nibble of the address
+            0xFEA0..=0xFEFF if self.model.is_cgb() => {
+                let high = addr as u8 & 0xF0;
+                high | high >> 4
+            }
+            0xFEA0..=0xFEFF => 0x00,
+
+            0xFF70 => 0xF8 | self.svbk,
+            0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize],
+            _ => unreachable!("Not an MMU address: 0x{:04X}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0xC000..=0xFDFF => {
+                let i = self.wram_index(addr);
+                self.wram[i] = value;
+            }
+            0xFEA0..=0xFEFF => {}
+            0xFF70 => self.svbk = value & 0x07,
+            0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize] = value,
+            _ => unreachable!("Not an MMU address: 0x{:04X}", addr),
+        }
+    }
+}
+
+impl Default for Mmu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,13 @@
+// which console is being emulated
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Model {
+    #[default]
+    Dmg,
+    Cgb,
+}
+
+impl Model {
+    pub fn is_cgb(self) -> bool {
+        self == Model::Cgb
+    }
+}
@@ -0,0 +1,440 @@
+use std::collections::VecDeque;
+
+use super::{BgPixel, Ppu, SCREEN_WIDTH};
+
+// cost of fetching one sprite's tile row
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+// what a sprite at X=0, entirely off the left edge, always costs
+const HIDDEN_SPRITE_DOTS: u8 = 11;
+
+// the BG fetcher spends 2 dots on each step before trying to push
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+enum FetchStep {
+    #[default]
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct ObjPixel {
+    color: u8,
+    flags: u8,
+    index: usize, // OAM index, decides overlaps on CGB
+}
+
+// state of mode 3 for the current line
+#[derive(Default)]
+pub struct PixelFifo {
+    bg: VecDeque<BgPixel>,
+    obj: VecDeque<ObjPixel>,
+
+    step: FetchStep,
+    step_dots: u8,
+    tile_x: u8, // next map column, relative to SCX or the window's left edge
+    tile: u8,
+    attrs: u8,
+    lo: u8,
+    hi: u8,
+    // the first fetch of every line is thrown away
+    dummy_fetch: bool,
+
+    lx: u8,      // next screen x to output
+    discard: u8, // pixels still to drop for SCX fine scroll
+    window: bool,
+
+    // sprites are sorted by X, so they're fetched in list order
+    next_sprite: usize,
+    sprite_fetch: Option<(usize, u8)>, // sprite being fetched and dots left
+    // the last BG or window tile a sprite waited on the fetcher for
+    waited_tile: Option<(bool, i16)>,
+}
+
+impl Ppu {
+    pub(super) fn start_fifo_line(&mut self) {
+        let f = &mut self.fifo;
+
+        f.bg.clear();
+        f.obj.clear();
+        f.step = FetchStep::Tile;
+        f.step_dots = 0;
+        f.tile_x = 0;
+        f.dummy_fetch = true;
+        f.lx = 0;
+        // SCX's lower bits are only looked at once, at the start of the line
+        f.discard = self.scx % 8;
+        f.window = false;
+        f.next_sprite = 0;
+        f.sprite_fetch = None;
+        f.waited_tile = None;
+    }
+
+    // runs one dot of mode 3, returns true once the line is complete
+    pub(super) fn fifo_dot(&mut self) -> bool {
+        let mut fifo = std::mem::take(&mut self.fifo);
+        let done = self.step_fifo(&mut fifo);
+        self.fifo = fifo;
+
+        done
+    }
+
+    fn step_fifo(&mut self, f: &mut PixelFifo) -> bool {
+        // reaching WX restarts the fetcher on the window map, which
+        // empties the FIFO and costs a fresh tile fetch. Nothing is checked
+        // before the first tile is in, so WX=7 pays for that too
+        let window_enabled = self.lcdc & 0x01 != 0 || self.model.is_cgb();
+        if !f.window
+            && window_enabled
+            && self.window_drawn()
+            && f.lx + 7 >= self.wx
+            && !f.bg.is_empty()
+        {
+            f.window = true;
+            self.window_on_line = true;
+            f.bg.clear();
+            f.step = FetchStep::Tile;
+            f.step_dots = 0;
+            f.tile_x = 0;
+            // WX below 7 pushes the window's left edge off screen
+            f.discard = 7u8.saturating_sub(self.wx);
+        }
+
+        // a sprite starting at this pixel has to be fetched first, once
+        // there's a BG tile for it to go over
+        if f.sprite_fetch.is_none()
+            && self.lcdc & 0x02 != 0
+            && f.next_sprite < self.sprites.len()
+            && self.sprites[f.next_sprite].x <= f.lx + 8
+        {
+            if f.bg.is_empty() {
+                self.tick_fetcher(f);
+                return false;
+            }
+
+            let dots = self.sprite_dots(f, self.sprites[f.next_sprite].x);
+            f.sprite_fetch = Some((f.next_sprite, dots));
+            f.next_sprite += 1;
+        }
+
+        // a sprite fetch stalls both the BG fetcher and the pixel output
+        if let Some((index, dots)) = f.sprite_fetch {
+            if dots > 1 {
+                f.sprite_fetch = Some((index, dots - 1));
+            } else {
+                f.sprite_fetch = None;
+                self.merge_sprite(f, index);
+            }
+            return false;
+        }
+
+        if let Some(bg) = f.bg.pop_front() {
+            // discarded pixels only come out of the BG FIFO, sprites are
+            // already lined up with the screen
+            if f.discard > 0 {
+                f.discard -= 1;
+            } else {
+                // palettes and LCDC are sampled as each pixel leaves the FIFO
+                let sprite = f
+                    .obj
+                    .pop_front()
+                    .filter(|p| p.color != 0 && self.lcdc & 0x02 != 0)
+                    .map(|p| (p.color, p.flags));
+                let i = self.ly as usize * SCREEN_WIDTH + f.lx as usize;
+                self.framebuffer[i] = self.mix_pixel(bg, sprite);
+                f.lx += 1;
+            }
+        }
+
+        self.tick_fetcher(f);
+
+        f.lx as usize == SCREEN_WIDTH
+    }
+
+    fn tick_fetcher(&self, f: &mut PixelFifo) {
+        if f.step != FetchStep::Push {
+            f.step_dots += 1;
+            if f.step_dots < 2 {
+                return;
+            }
+            f.step_dots = 0;
+
+            // registers are read at the step that needs them, so mid-line
+            // changes to SCX/SCY/LCDC land on the next tile fetched
+            match f.step {
+                FetchStep::Tile => {
+                    (f.tile, f.attrs) = if f.window {
+                        self.map_entry(self.lcdc & 0x40 != 0, f.tile_x, self.window_line / 8)
+                    } else {
+                        let x = (self.scx / 8).wrapping_add(f.tile_x);
+                        let y = self.ly.wrapping_add(self.scy) / 8;
+                        self.map_entry(self.lcdc & 0x08 != 0, x, y)
+                    };
+                    f.step = FetchStep::DataLow;
+                }
+                FetchStep::DataLow => {
+                    f.lo = self.vram[self.fetcher_row_address(f)];
+                    f.step = FetchStep::DataHigh;
+                }
+                FetchStep::DataHigh => {
+                    f.hi = self.vram[self.fetcher_row_address(f) + 1];
+                    f.step = FetchStep::Push;
+                }
+                FetchStep::Push => unreachable!(),
+            }
+        }
+
+        // pushing only works into an empty FIFO and is tried every dot,
+        // including the one the high byte arrives on
+        if f.step == FetchStep::Push && f.bg.is_empty() {
+            if f.dummy_fetch {
+                f.dummy_fetch = false;
+            } else {
+                for column in 0..8 {
+                    // CGB attribute bit 5 mirrors the tile horizontally
+                    let bit = if f.attrs & 0x20 != 0 {
+                        column
+                    } else {
+                        7 - column
+                    };
+                    f.bg.push_back(BgPixel {
+                        color: ((f.hi >> bit) & 1) << 1 | ((f.lo >> bit) & 1),
+                        attrs: f.attrs,
+                    });
+                }
+                f.tile_x = f.tile_x.wrapping_add(1);
+            }
+            f.step = FetchStep::Tile;
+        }
+    }
+
+    // how long a sprite at `x` holds up mode 3: the fetch itself, after
+    // waiting for the BG fetcher to finish the tile under the sprite's
+    // left edge. That wait is only paid once per tile, and gets shorter
+    // the further into the tile the sprite starts. Sprites from X=168 on
+    // are never reached and cost nothing
+    fn sprite_dots(&self, f: &mut PixelFifo, x: u8) -> u8 {
+        if x == 0 {
+            return HIDDEN_SPRITE_DOTS;
+        }
+
+        // the sprite's left edge, in the BG or window's own columns
+        let left = x as i16 - 8;
+        let column = if f.window {
+            left - (self.wx as i16 - 7)
+        } else {
+            left + (self.scx % 8) as i16
+        };
+
+        let tile = (f.window, column.div_euclid(8));
+        if f.waited_tile == Some(tile) {
+            return SPRITE_FETCH_DOTS;
+        }
+        f.waited_tile = Some(tile);
+
+        let pixels_right = 7 - column.rem_euclid(8) as u8;
+        SPRITE_FETCH_DOTS + pixels_right.saturating_sub(2)
+    }
+
+    fn fetcher_row_address(&self, f: &PixelFifo) -> usize {
+        let mut row = if f.window {
+            self.window_line % 8
+        } else {
+            self.ly.wrapping_add(self.scy) % 8
+        };
+
+        // CGB attribute bit 6 mirrors the tile vertically
+        if f.attrs & 0x40 != 0 {
+            row = 7 - row;
+        }
+
+        self.bg_tile_address(f.tile, f.attrs) + row as usize * 2
+    }
+
+    // mixes a fetched sprite into the OBJ FIFO. Pixels already there win
+    // unless they're transparent, which gives the DMG X-then-OAM priority.
+    // The CGB also lets a sprite earlier in OAM take over
+    fn merge_sprite(&self, f: &mut PixelFifo, index: usize) {
+        let sprite = self.sprites[index];
+        // sprites hanging off the left edge lose their first columns
+        let skip = (f.lx + 8).saturating_sub(sprite.x);
+
+        for (i, column) in (skip..8).enumerate() {
+            let pixel = ObjPixel {
+                color: self.sprite_color(&sprite, column),
+                flags: sprite.flags,
+                index: sprite.index,
+            };
+
+            match f.obj.get_mut(i) {
+                Some(existing) if existing.color == 0 => *existing = pixel,
+                Some(existing)
+                    if self.model.is_cgb() && pixel.color != 0 && pixel.index < existing.index =>
+                {
+                    *existing = pixel
+                }
+                Some(_) => {}
+                None => f.obj.push_back(pixel),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{DMG_COLORS, DOTS_PER_LINE, Mode, RenderMode};
+    use super::*;
+
+    // a solid black sprite at the left edge of line 0, over a blank BG
+    fn ppu_with_sprite(render_mode: RenderMode) -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.set_render_mode(render_mode);
+        ppu.write_register(0xFF40, 0x93);
+        for addr in 0x8010..0x8020 {
+            ppu.write_vram(addr, 0xFF);
+        }
+        for (i, value) in [16, 8, 1, 0].into_iter().enumerate() {
+            ppu.write_oam(0xFE00 + i as u16, value);
+        }
+
+        ppu
+    }
+
+    // columns of line 0 the sprite ended up on
+    fn sprite_columns(ppu: &mut Ppu) -> Vec<usize> {
+        for _ in 0..DOTS_PER_LINE / 4 {
+            ppu.tick(4);
+        }
+
+        (0..SCREEN_WIDTH)
+            .filter(|&x| ppu.framebuffer()[x] == DMG_COLORS[3])
+            .collect()
+    }
+
+    #[test]
+    fn fine_scroll_doesnt_eat_sprite_pixels() {
+        for render_mode in [RenderMode::Scanline, RenderMode::PixelFifo] {
+            let mut ppu = ppu_with_sprite(render_mode);
+            ppu.write_register(0xFF43, 3);
+
+            assert_eq!(sprite_columns(&mut ppu), (0..8).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn window_left_of_the_screen_doesnt_eat_sprite_pixels() {
+        let mut ppu = ppu_with_sprite(RenderMode::PixelFifo);
+        ppu.write_register(0xFF40, 0xB3);
+        ppu.write_register(0xFF4B, 3);
+
+        assert_eq!(sprite_columns(&mut ppu), (0..8).collect::<Vec<_>>());
+    }
+
+    // dots line 0 spends in mode 3 on the FIFO renderer, with BG and
+    // sprites on
+    fn mode3_dots(setup: impl Fn(&mut Ppu)) -> usize {
+        let mut ppu = Ppu::new();
+        ppu.set_render_mode(RenderMode::PixelFifo);
+        ppu.write_register(0xFF40, 0x93);
+        setup(&mut ppu);
+
+        (0..DOTS_PER_LINE)
+            .filter(|_| {
+                ppu.tick(1);
+                ppu.mode() == Mode::Drawing
+            })
+            .count()
+    }
+
+    fn with_sprites(ppu: &mut Ppu, xs: &[u8]) {
+        for (i, &x) in xs.iter().enumerate() {
+            ppu.write_oam(0xFE00 + i as u16 * 4, 16);
+            ppu.write_oam(0xFE01 + i as u16 * 4, x);
+        }
+    }
+
+    #[test]
+    fn mode_3_takes_172_dots_plus_fine_scroll() {
+        for scx in 0..8 {
+            let dots = mode3_dots(|ppu| ppu.write_register(0xFF43, scx));
+            assert_eq!(dots, 172 + scx as usize, "SCX={scx}");
+        }
+    }
+
+    #[test]
+    fn starting_the_window_costs_6_dots() {
+        for wx in [7, 8, 80] {
+            let dots = mode3_dots(|ppu| {
+                ppu.write_register(0xFF40, 0xB3);
+                ppu.write_register(0xFF4B, wx);
+            });
+            assert_eq!(dots, 178, "WX={wx}");
+        }
+    }
+
+    #[test]
+    fn sprite_cost_depends_on_where_it_starts_in_the_bg_tile() {
+        // X, SCX, extra dots
+        let sprites = [
+            (8, 0, 11),
+            (9, 0, 10),
+            (12, 0, 7),
+            (13, 0, 6),
+            (15, 0, 6),
+            (8, 3, 8),
+            (13, 3, 11),
+            // partly off the left edge
+            (4, 0, 7),
+            // entirely off the left edge
+            (0, 0, 11),
+            (0, 3, 11),
+            // partly off the right edge
+            (160, 0, 11),
+            (167, 0, 6),
+            // entirely off the right edge, never reached
+            (168, 0, 0),
+            (255, 0, 0),
+        ];
+
+        for (x, scx, extra) in sprites {
+            let dots = mode3_dots(|ppu| {
+                ppu.write_register(0xFF43, scx);
+                with_sprites(ppu, &[x]);
+            });
+            assert_eq!(dots, 172 + scx as usize + extra, "X={x} SCX={scx}");
+        }
+    }
+
+    #[test]
+    fn only_the_first_sprite_on_a_tile_waits_for_the_fetcher() {
+        assert_eq!(mode3_dots(|ppu| with_sprites(ppu, &[8, 10])), 172 + 11 + 6);
+        assert_eq!(mode3_dots(|ppu| with_sprites(ppu, &[8, 16])), 172 + 11 + 11);
+        assert_eq!(
+            mode3_dots(|ppu| with_sprites(ppu, &[8; 10])),
+            172 + 11 + 9 * 6
+        );
+        assert_eq!(mode3_dots(|ppu| with_sprites(ppu, &[0; 10])), 172 + 10 * 11);
+    }
+
+    #[test]
+    fn sprites_over_the_window_line_up_with_its_tiles() {
+        // the window starts at X=80 (WX=87), a sprite at X=88 sits on
+        // the first column of its first tile
+        let dots = mode3_dots(|ppu| {
+            ppu.write_register(0xFF40, 0xB3);
+            ppu.write_register(0xFF4B, 87);
+            with_sprites(ppu, &[88]);
+        });
+        assert_eq!(dots, 172 + 6 + 11);
+    }
+
+    #[test]
+    fn disabled_sprites_cost_nothing() {
+        let dots = mode3_dots(|ppu| {
+            ppu.write_register(0xFF40, 0x91);
+            with_sprites(ppu, &[8, 16, 24]);
+        });
+        assert_eq!(dots, 172);
+    }
+}
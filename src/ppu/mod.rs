@@ -0,0 +1,1031 @@
+mod fifo;
+
+use crate::bus::{Clock, MemoryMappedDevice};
+use crate::interrupts::Interrupt;
+use crate::model::Model;
+use fifo::PixelFifo;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+const DOTS_PER_LINE: u16 = 456;
+const OAM_SCAN_DOTS: u16 = 80;
+const DRAWING_DOTS: u16 = 172;
+const LINES_PER_FRAME: u8 = 154;
+const MAX_SPRITES_PER_LINE: usize = 10;
+
+// how mode 3 is emulated
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RenderMode {
+    // draws the whole line at once with a fixed mode 3 length, fast but
+    // blind to register changes in the middle of a line
+    Scanline,
+    // models the fetchers and pixel FIFO dot by dot
+    PixelFifo,
+}
+
+// DMG shades as 0x00RRGGBB, lightest first
+const DMG_COLORS: [u32; 4] = [0x00E0F8D0, 0x0088C070, 0x00346856, 0x00081820];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+// a BG/window pixel: colour index plus its CGB map attributes (0 on DMG)
+#[derive(Debug, Default, Copy, Clone)]
+struct BgPixel {
+    color: u8,
+    attrs: u8,
+}
+
+// one OAM entry
+#[derive(Debug, Copy, Clone)]
+struct Sprite {
+    y: u8, // screen y + 16
+    x: u8, // screen x + 8
+    tile: u8,
+    flags: u8,
+    index: usize, // position in OAM
+}
+
+pub struct Ppu {
+    model: Model,
+
+    // two banks of 8 KiB on CGB, bank 1 holds the BG map attributes
+    vram: [u8; 0x4000],
+    vram_bank: u8, // 0xFF4F (VBK)
+    oam: [u8; 0xA0],
+
+    // CGB palette RAM, 8 palettes of 4 RGB555 colours each
+    bg_palettes: [u8; 64],
+    obj_palettes: [u8; 64],
+    bcps: u8, // 0xFF68, index + auto increment
+    ocps: u8, // 0xFF6A
+
+    // registers
+    lcdc: u8, // 0xFF40
+    stat: u8, // 0xFF41, only the interrupt enable bits 3-6
+    scy: u8,  // 0xFF42
+    scx: u8,  // 0xFF43
+    ly: u8,   // 0xFF44
+    lyc: u8,  // 0xFF45
+    bgp: u8,  // 0xFF47
+    obp0: u8, // 0xFF48
+    obp1: u8, // 0xFF49
+    wy: u8,   // 0xFF4A
+    wx: u8,   // 0xFF4B
+
+    mode: Mode,
+    dot: u16, // position within the current line
+
+    // the window keeps its own line counter that only advances on lines
+    // where it was actually drawn
+    window_line: u8,
+    window_triggered: bool, // WY matched LY at some point this frame
+    window_on_line: bool,   // the window was drawn on the current line
+
+    sprites: Vec<Sprite>, // picked during OAM scan for the current line
+
+    render_mode: RenderMode,
+    fifo: PixelFifo,
+
+    stat_line: bool, // combined STAT interrupt sources
+    interrupts: u8,  // IF bits requested, drained by the bus
+
+    framebuffer: Box<[u32; SCREEN_WIDTH * SCREEN_HEIGHT]>,
+    frame_ready: bool,
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Self::with_model(Model::Dmg)
+    }
+
+    pub fn with_model(model: Model) -> Self {
+        Self {
+            model,
+            vram: [0; 0x4000],
+            vram_bank: 0,
+            oam: [0; 0xA0],
+            // the CGB boot ROM leaves the BG palettes white
+            bg_palettes: [0xFF; 64],
+            obj_palettes: [0; 64],
+            bcps: 0,
+            ocps: 0,
+            lcdc: 0x91,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0xFC,
+            obp0: 0xFF,
+            obp1: 0xFF,
+            wy: 0,
+            wx: 0,
+            mode: Mode::OamScan,
+            dot: 0,
+            window_line: 0,
+            window_triggered: false,
+            window_on_line: false,
+            sprites: Vec::with_capacity(MAX_SPRITES_PER_LINE),
+            render_mode: RenderMode::Scanline,
+            fifo: PixelFifo::default(),
+            stat_line: false,
+            interrupts: 0,
+            framebuffer: Box::new([DMG_COLORS[0]; SCREEN_WIDTH * SCREEN_HEIGHT]),
+            frame_ready: false,
+        }
+    }
+
+    pub fn set_render_mode(&mut self, mode: RenderMode) {
+        self.render_mode = mode;
+    }
+
+    pub fn framebuffer(&self) -> &[u32] {
+        &self.framebuffer[..]
+    }
+
+    // true once per frame, when VBlank starts
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    fn lcd_enabled(&self) -> bool {
+        self.lcdc & 0x80 != 0
+    }
+
+    // advances by `cycles` dots
+    pub fn tick(&mut self, cycles: u8) {
+        if !self.lcd_enabled() {
+            return;
+        }
+
+        for _ in 0..cycles {
+            self.dot += 1;
+
+            match (self.mode, self.render_mode) {
+                (Mode::OamScan, _) if self.dot == OAM_SCAN_DOTS => {
+                    self.scan_oam();
+                    if self.ly == self.wy {
+                        self.window_triggered = true;
+                    }
+                    if self.render_mode == RenderMode::PixelFifo {
+                        self.start_fifo_line();
+                    }
+                    self.set_mode(Mode::Drawing);
+                }
+                (Mode::Drawing, RenderMode::Scanline)
+                    if self.dot == OAM_SCAN_DOTS + DRAWING_DOTS =>
+                {
+                    self.render_line();
+                    self.set_mode(Mode::HBlank);
+                }
+                (Mode::Drawing, RenderMode::PixelFifo) if self.fifo_dot() => {
+                    self.set_mode(Mode::HBlank);
+                }
+                _ => {}
+            }
+
+            if self.dot == DOTS_PER_LINE {
+                self.dot = 0;
+                self.next_line();
+            }
+        }
+    }
+
+    // returns and clears the IF bits of interrupts raised since the last call
+    pub fn take_interrupts(&mut self) -> u8 {
+        std::mem::take(&mut self.interrupts)
+    }
+
+    fn next_line(&mut self) {
+        if std::mem::take(&mut self.window_on_line) {
+            self.window_line += 1;
+        }
+
+        self.ly += 1;
+        if self.ly == LINES_PER_FRAME {
+            self.ly = 0;
+            self.window_line = 0;
+            self.window_triggered = false;
+        }
+
+        if self.ly == SCREEN_HEIGHT as u8 {
+            self.frame_ready = true;
+            self.interrupts |= Interrupt::VBlank.mask();
+            self.set_mode(Mode::VBlank);
+        } else if self.ly < SCREEN_HEIGHT as u8 {
+            self.set_mode(Mode::OamScan);
+        } else {
+            // still VBlank, but LY changed so LY=LYC has to be checked again
+            self.update_stat_line();
+        }
+    }
+
+    fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.update_stat_line();
+    }
+
+    // level of the internal STAT interrupt line for a given set of enable
+    // bits: every enabled source ORed together
+    fn stat_line_level(&self, enables: u8) -> bool {
+        if !self.lcd_enabled() {
+            return false;
+        }
+
+        let mode_source = match self.mode {
+            Mode::HBlank => 0x08,
+            Mode::VBlank => 0x10,
+            Mode::OamScan => 0x20,
+            Mode::Drawing => 0x00, // no interrupt for mode 3
+        };
+        let coincidence = self.ly == self.lyc && enables & 0x40 != 0;
+
+        enables & mode_source != 0 || coincidence
+    }
+
+    // the interrupt is only requested on a rising edge of the combined line,
+    // so a source becoming active while another one already holds the line
+    // high doesn't fire again ("STAT blocking")
+    fn update_stat_line(&mut self) {
+        let level = self.stat_line_level(self.stat);
+        if level && !self.stat_line {
+            self.interrupts |= Interrupt::LcdStat.mask();
+        }
+        self.stat_line = level;
+    }
+
+    // picks the first 10 sprites in OAM that overlap this line
+    fn scan_oam(&mut self) {
+        let height = self.sprite_height();
+
+        self.sprites.clear();
+        for index in 0..40 {
+            let entry = &self.oam[index * 4..index * 4 + 4];
+            let top = entry[0] as i16 - 16;
+            let line = self.ly as i16;
+
+            if line >= top && line < top + height as i16 {
+                self.sprites.push(Sprite {
+                    y: entry[0],
+                    x: entry[1],
+                    tile: entry[2],
+                    flags: entry[3],
+                    index,
+                });
+                if self.sprites.len() == MAX_SPRITES_PER_LINE {
+                    break;
+                }
+            }
+        }
+
+        // on DMG the sprite with the smaller X wins, then the one earlier in OAM.
+        // The FIFO also needs them in X order to know when to fetch them
+        self.sprites.sort_by_key(|s| (s.x, s.index));
+    }
+
+    fn sprite_height(&self) -> u8 {
+        if self.lcdc & 0x04 != 0 { 16 } else { 8 }
+    }
+
+    fn window_drawn(&self) -> bool {
+        self.lcdc & 0x20 != 0 && self.window_triggered && self.wx <= 166
+    }
+
+    fn render_line(&mut self) {
+        // on CGB LCDC bit 0 only takes away the BG's priority over sprites
+        let bg_enabled = self.lcdc & 0x01 != 0 || self.model.is_cgb();
+        let window = bg_enabled && self.window_drawn();
+        self.window_on_line = window;
+        let y = self.ly as usize;
+
+        for x in 0..SCREEN_WIDTH {
+            // colour index before the palette, needed for sprite priority
+            let bg = if !bg_enabled {
+                BgPixel::default()
+            } else if window && x + 7 >= self.wx as usize {
+                let wx = (x + 7 - self.wx as usize) as u8;
+                self.bg_pixel(self.lcdc & 0x40 != 0, wx, self.window_line)
+            } else {
+                let bx = (x as u8).wrapping_add(self.scx);
+                let by = self.ly.wrapping_add(self.scy);
+                self.bg_pixel(self.lcdc & 0x08 != 0, bx, by)
+            };
+
+            let sprite = self.sprite_pixel(x as u8);
+            self.framebuffer[y * SCREEN_WIDTH + x] = self.mix_pixel(bg, sprite);
+        }
+    }
+
+    // final colour of a BG pixel and an opaque sprite pixel given as
+    // (colour index, flags), with the palettes as they are now
+    fn mix_pixel(&self, bg: BgPixel, sprite: Option<(u8, u8)>) -> u32 {
+        if self.model.is_cgb() {
+            return self.mix_pixel_cgb(bg, sprite);
+        }
+
+        // with LCDC bit 0 clear the DMG shows colour 0 in place of BG and window
+        let bg_color = if self.lcdc & 0x01 != 0 { bg.color } else { 0 };
+        let mut shade = (self.bgp >> (bg_color * 2)) & 0x03;
+
+        if let Some((color, flags)) = sprite {
+            // BG priority flag puts the sprite behind BG colours 1-3
+            if flags & 0x80 == 0 || bg_color == 0 {
+                let palette = if flags & 0x10 != 0 {
+                    self.obp1
+                } else {
+                    self.obp0
+                };
+                shade = (palette >> (color * 2)) & 0x03;
+            }
+        }
+
+        DMG_COLORS[shade as usize]
+    }
+
+    fn mix_pixel_cgb(&self, bg: BgPixel, sprite: Option<(u8, u8)>) -> u32 {
+        if let Some((color, flags)) = sprite {
+            // LCDC bit 0 clear puts sprites on top no matter what, otherwise
+            // either priority bit hands BG colours 1-3 the win
+            let sprite_on_top = self.lcdc & 0x01 == 0
+                || bg.color == 0
+                || (bg.attrs & 0x80 == 0 && flags & 0x80 == 0);
+
+            if sprite_on_top {
+                return cgb_color(&self.obj_palettes, flags & 0x07, color);
+            }
+        }
+
+        cgb_color(&self.bg_palettes, bg.attrs & 0x07, bg.color)
+    }
+
+    // pixel (x, y) of a 256x256 background/window map
+    fn bg_pixel(&self, high_map: bool, x: u8, y: u8) -> BgPixel {
+        let (tile, attrs) = self.map_entry(high_map, x / 8, y / 8);
+        let address = self.bg_tile_address(tile, attrs);
+
+        let column = if attrs & 0x20 != 0 { 7 - x % 8 } else { x % 8 };
+        let row = if attrs & 0x40 != 0 { 7 - y % 8 } else { y % 8 };
+
+        BgPixel {
+            color: self.tile_pixel(address, column, row),
+            attrs,
+        }
+    }
+
+    // tile number and CGB attributes at column `x`, row `y` of one of the
+    // two 32x32 maps. The attributes sit at the same spot in VRAM bank 1
+    fn map_entry(&self, high_map: bool, x: u8, y: u8) -> (u8, u8) {
+        let map_base = if high_map { 0x1C00 } else { 0x1800 };
+        let map_index = map_base + (y as usize % 32) * 32 + (x as usize % 32);
+
+        let attrs = if self.model.is_cgb() {
+            self.vram[0x2000 + map_index]
+        } else {
+            0
+        };
+
+        (self.vram[map_index], attrs)
+    }
+
+    // LCDC bit 4 picks between 0x8000 unsigned and 0x9000 signed addressing,
+    // CGB attribute bit 3 picks the VRAM bank
+    fn bg_tile_address(&self, tile: u8, attrs: u8) -> usize {
+        let bank = if attrs & 0x08 != 0 { 0x2000 } else { 0 };
+
+        if self.lcdc & 0x10 != 0 {
+            bank + tile as usize * 16
+        } else {
+            bank + (0x1000 + (tile as i8 as i32) * 16) as usize
+        }
+    }
+
+    fn tile_pixel(&self, tile_address: usize, x: u8, y: u8) -> u8 {
+        let lo = self.vram[tile_address + y as usize * 2];
+        let hi = self.vram[tile_address + y as usize * 2 + 1];
+        let bit = 7 - x;
+
+        ((hi >> bit) & 1) << 1 | ((lo >> bit) & 1)
+    }
+
+    // first opaque sprite pixel at screen x, as (colour index, flags)
+    fn sprite_pixel(&self, x: u8) -> Option<(u8, u8)> {
+        if self.lcdc & 0x02 == 0 {
+            return None;
+        }
+
+        // the list is in DMG priority order (X, then OAM index), the CGB
+        // only looks at the OAM index
+        let mut candidates = self.sprites.iter().filter_map(|sprite| {
+            let left = sprite.x as i16 - 8;
+            let column = x as i16 - left;
+            if !(0..8).contains(&column) {
+                return None;
+            }
+
+            let color = self.sprite_color(sprite, column as u8);
+            (color != 0).then_some((sprite.index, color, sprite.flags))
+        });
+
+        let winner = if self.model.is_cgb() {
+            candidates.min_by_key(|&(index, _, _)| index)
+        } else {
+            candidates.next()
+        };
+
+        winner.map(|(_, color, flags)| (color, flags))
+    }
+
+    // colour index of `column` (0-7, left to right on screen) of a sprite
+    // on the current line, with flipping applied
+    fn sprite_color(&self, sprite: &Sprite, column: u8) -> u8 {
+        let height = self.sprite_height();
+
+        let mut row = (self.ly as i16 - (sprite.y as i16 - 16)) as u8;
+        let mut column = column;
+        if sprite.flags & 0x40 != 0 {
+            row = height - 1 - row;
+        }
+        if sprite.flags & 0x20 != 0 {
+            column = 7 - column;
+        }
+
+        // 8x16 sprites ignore bit 0 of the tile index
+        let tile = if height == 16 {
+            sprite.tile & 0xFE
+        } else {
+            sprite.tile
+        };
+
+        // sprites always use 0x8000 addressing, on CGB flag bit 3 picks the bank
+        let bank = if self.model.is_cgb() && sprite.flags & 0x08 != 0 {
+            0x2000
+        } else {
+            0
+        };
+
+        self.tile_pixel(bank + tile as usize * 16, column, row)
+    }
+
+    fn vram_index(&self, addr: u16) -> usize {
+        self.vram_bank as usize * 0x2000 + (addr - 0x8000) as usize
+    }
+
+    pub fn read_vram(&self, addr: u16) -> u8 {
+        self.vram[self.vram_index(addr)]
+    }
+
+    pub fn write_vram(&mut self, addr: u16, value: u8) {
+        let i = self.vram_index(addr);
+        self.vram[i] = value;
+    }
+
+    pub fn read_oam(&self, addr: u16) -> u8 {
+        self.oam[(addr - 0xFE00) as usize]
+    }
+
+    pub fn write_oam(&mut self, addr: u16, value: u8) {
+        self.oam[(addr - 0xFE00) as usize] = value;
+    }
+
+    pub fn read_register(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF40 => self.lcdc,
+            0xFF41 => {
+                let coincidence = if self.ly == self.lyc { 0x04 } else { 0 };
+                let mode = if self.lcd_enabled() {
+                    self.mode as u8
+                } else {
+                    0
+                };
+                0x80 | self.stat | coincidence | mode
+            }
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+
+            // CGB only registers
+            _ if !self.model.is_cgb() => 0xFF,
+            0xFF4F => 0xFE | self.vram_bank,
+            0xFF68 => 0x40 | self.bcps,
+            0xFF69 => self.bg_palettes[(self.bcps & 0x3F) as usize],
+            0xFF6A => 0x40 | self.ocps,
+            0xFF6B => self.obj_palettes[(self.ocps & 0x3F) as usize],
+            _ => unreachable!("Not a PPU register: 0x{:04X}", addr),
+        }
+    }
+
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0xFF40 => {
+                let was_enabled = self.lcd_enabled();
+                self.lcdc = value;
+
+                if was_enabled && !self.lcd_enabled() {
+                    // turning the LCD off resets it to the top of the screen
+                    self.ly = 0;
+                    self.dot = 0;
+                    self.mode = Mode::HBlank;
+                    self.window_line = 0;
+                    self.window_triggered = false;
+                    self.window_on_line = false;
+                } else if !was_enabled && self.lcd_enabled() {
+                    self.mode = Mode::OamScan;
+                }
+                self.update_stat_line();
+            }
+            0xFF41 => {
+                // DMG quirk: for one cycle the write behaves as if the
+                // HBlank, VBlank and LY=LYC sources were enabled, so it can
+                // fire in those even when none of them are selected. OAM
+                // scan isn't part of it
+                if !self.model.is_cgb() && self.stat_line_level(0x58) && !self.stat_line {
+                    self.interrupts |= Interrupt::LcdStat.mask();
+                    self.stat_line = true;
+                }
+
+                self.stat = value & 0x78;
+                self.update_stat_line();
+            }
+            0xFF42 => self.scy = value,
+            0xFF43 => self.scx = value,
+            0xFF44 => {} // LY is read only
+            0xFF45 => {
+                self.lyc = value;
+                self.update_stat_line();
+            }
+            0xFF47 => self.bgp = value,
+            0xFF48 => self.obp0 = value,
+            0xFF49 => self.obp1 = value,
+            0xFF4A => self.wy = value,
+            0xFF4B => self.wx = value,
+
+            // CGB only registers
+            _ if !self.model.is_cgb() => {}
+            0xFF4F => self.vram_bank = value & 0x01,
+            0xFF68 => self.bcps = value & 0xBF,
+            0xFF69 => write_palette(&mut self.bg_palettes, &mut self.bcps, value),
+            0xFF6A => self.ocps = value & 0xBF,
+            0xFF6B => write_palette(&mut self.obj_palettes, &mut self.ocps, value),
+            _ => unreachable!("Not a PPU register: 0x{:04X}", addr),
+        }
+    }
+}
+
+impl MemoryMappedDevice for Ppu {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0x9FFF => self.read_vram(addr),
+            0xFE00..=0xFE9F => self.read_oam(addr),
+            _ => self.read_register(addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000..=0x9FFF => self.write_vram(addr, value),
+            0xFE00..=0xFE9F => self.write_oam(addr, value),
+            _ => self.write_register(addr, value),
+        }
+    }
+
+    // the PPU runs in real time, so only half as many dots go by per
+    // M-cycle in double speed
+    fn tick(&mut self, clock: &Clock) -> u8 {
+        Ppu::tick(self, clock.real_cycles);
+        self.take_interrupts()
+    }
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// writes through BCPD/OCPD, bumping the index when BCPS/OCPS bit 7 is set
+fn write_palette(palettes: &mut [u8; 64], spec: &mut u8, value: u8) {
+    let index = *spec & 0x3F;
+    palettes[index as usize] = value;
+
+    if *spec & 0x80 != 0 {
+        *spec = 0x80 | ((index + 1) & 0x3F);
+    }
+}
+
+// RGB555 palette entry as 0x00RRGGBB
+fn cgb_color(palettes: &[u8; 64], palette: u8, color: u8) -> u32 {
+    let i = palette as usize * 8 + color as usize * 2;
+    let rgb555 = (palettes[i + 1] as u32) << 8 | palettes[i] as u32;
+
+    // stretch each 5-bit channel to 8 bits
+    let channel = |shift: u32| {
+        let c = (rgb555 >> shift) & 0x1F;
+        (c << 3) | (c >> 2)
+    };
+
+    channel(0) << 16 | channel(5) << 8 | channel(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_dots(ppu: &mut Ppu, dots: u16) {
+        for _ in 0..dots {
+            ppu.tick(1);
+        }
+    }
+
+    #[test]
+    fn dmg_stat_write_fires_in_hblank_but_not_oam_scan() {
+        let mut ppu = Ppu::new();
+        ppu.write_register(0xFF45, 0x80);
+
+        assert_eq!(ppu.mode(), Mode::OamScan);
+        ppu.write_register(0xFF41, 0x00);
+        assert_eq!(ppu.take_interrupts(), 0);
+
+        run_dots(&mut ppu, OAM_SCAN_DOTS + DRAWING_DOTS);
+        assert_eq!(ppu.mode(), Mode::HBlank);
+        ppu.write_register(0xFF41, 0x00);
+        assert_eq!(ppu.take_interrupts(), Interrupt::LcdStat.mask());
+    }
+
+    #[test]
+    fn cgb_stat_write_has_no_quirk() {
+        let mut ppu = Ppu::with_model(Model::Cgb);
+        ppu.write_register(0xFF45, 0x80);
+
+        run_dots(&mut ppu, OAM_SCAN_DOTS + DRAWING_DOTS);
+        ppu.write_register(0xFF41, 0x00);
+        assert_eq!(ppu.take_interrupts(), 0);
+    }
+
+    const RENDER_MODES: [RenderMode; 2] = [RenderMode::Scanline, RenderMode::PixelFifo];
+
+    // a DMG with BGP, OBP0 and OBP1 mapping colour n to shade n
+    fn dmg(render_mode: RenderMode) -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.set_render_mode(render_mode);
+        for addr in [0xFF47, 0xFF48, 0xFF49] {
+            ppu.write_register(addr, 0xE4);
+        }
+
+        ppu
+    }
+
+    // every pixel of tile `tile` at 0x8000 addressing set to `color`, or
+    // only the leftmost column with `left_only`
+    fn fill_tile(ppu: &mut Ppu, tile: u8, color: u8, left_only: bool) {
+        let mask = if left_only { 0x80 } else { 0xFF };
+        let base = 0x8000 + tile as u16 * 16;
+        for row in 0..8 {
+            let lo = if color & 1 != 0 { mask } else { 0 };
+            let hi = if color & 2 != 0 { mask } else { 0 };
+            ppu.write_vram(base + row * 2, lo);
+            ppu.write_vram(base + row * 2 + 1, hi);
+        }
+    }
+
+    fn sprite(ppu: &mut Ppu, index: u16, x: u8, tile: u8, flags: u8) {
+        let addr = 0xFE00 + index * 4;
+        for (i, value) in [16, x, tile, flags].into_iter().enumerate() {
+            ppu.write_oam(addr + i as u16, value);
+        }
+    }
+
+    // runs line 0 and returns it
+    fn render(ppu: &mut Ppu) -> Vec<u32> {
+        run_dots(ppu, DOTS_PER_LINE);
+        ppu.framebuffer()[..SCREEN_WIDTH].to_vec()
+    }
+
+    // line 0 as DMG shades
+    fn shades(ppu: &mut Ppu) -> Vec<u8> {
+        render(ppu)
+            .iter()
+            .map(|color| DMG_COLORS.iter().position(|c| c == color).unwrap() as u8)
+            .collect()
+    }
+
+    // a line of `width` pixels of `shade` at `start`, shade 0 elsewhere
+    fn span(start: usize, width: usize, shade: u8) -> Vec<u8> {
+        (0..SCREEN_WIDTH)
+            .map(|x| {
+                if (start..start + width).contains(&x) {
+                    shade
+                } else {
+                    0
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dmg_bg_follows_the_map_scroll_and_bgp() {
+        for render_mode in RENDER_MODES {
+            let mut ppu = dmg(render_mode);
+            fill_tile(&mut ppu, 1, 1, false);
+            fill_tile(&mut ppu, 2, 2, false);
+            ppu.write_vram(0x9801, 1);
+            ppu.write_vram(0x9802, 2);
+            ppu.write_register(0xFF43, 12);
+            // colours 1 and 2 swap shades
+            ppu.write_register(0xFF47, 0xD8);
+
+            let mut expected = span(0, 4, 2);
+            expected[4..12].fill(1);
+            assert_eq!(shades(&mut ppu), expected, "{render_mode:?}");
+        }
+    }
+
+    #[test]
+    fn dmg_bg_signed_tile_addressing() {
+        for render_mode in RENDER_MODES {
+            let mut ppu = dmg(render_mode);
+            // tile 0xFF lives at 0x8FF0 with LCDC bit 4 clear
+            fill_tile(&mut ppu, 0xFF, 3, false);
+            ppu.write_vram(0x9800, 0xFF);
+            ppu.write_register(0xFF40, 0x81);
+
+            assert_eq!(shades(&mut ppu), span(0, 8, 3), "{render_mode:?}");
+        }
+    }
+
+    #[test]
+    fn dmg_window_covers_the_bg_from_wx() {
+        for render_mode in RENDER_MODES {
+            let mut ppu = dmg(render_mode);
+            fill_tile(&mut ppu, 1, 1, false);
+            fill_tile(&mut ppu, 2, 3, false);
+            ppu.write_vram(0x9800, 1);
+            ppu.write_vram(0x9C00, 2);
+            // window from 0x9C00, starting at x = 4
+            ppu.write_register(0xFF40, 0xF1);
+            ppu.write_register(0xFF4B, 11);
+
+            let mut expected = span(4, 8, 3);
+            expected[..4].fill(1);
+            assert_eq!(shades(&mut ppu), expected, "{render_mode:?}");
+        }
+    }
+
+    #[test]
+    fn dmg_sprites_use_their_palette_and_bg_priority() {
+        for render_mode in RENDER_MODES {
+            let mut ppu = dmg(render_mode);
+            fill_tile(&mut ppu, 1, 1, false);
+            fill_tile(&mut ppu, 2, 3, false);
+            ppu.write_vram(0x9801, 1);
+            ppu.write_register(0xFF40, 0x93);
+            // OBP1 maps colour 3 to shade 2
+            ppu.write_register(0xFF49, 0xA4);
+            sprite(&mut ppu, 0, 8, 2, 0x10);
+            // behind BG colours 1-3, so only left of the BG tile
+            sprite(&mut ppu, 1, 20, 2, 0x80);
+
+            let mut expected = span(0, 8, 2);
+            expected[8..16].fill(1);
+            expected[16..20].fill(3);
+            assert_eq!(shades(&mut ppu), expected, "{render_mode:?}");
+        }
+    }
+
+    #[test]
+    fn only_the_first_10_sprites_on_a_line_are_drawn() {
+        for render_mode in RENDER_MODES {
+            let mut ppu = dmg(render_mode);
+            fill_tile(&mut ppu, 1, 3, true);
+            ppu.write_register(0xFF40, 0x93);
+            // OAM order doesn't match X order
+            for i in 0..11 {
+                sprite(&mut ppu, i, 100 - i as u8 * 8, 1, 0);
+            }
+
+            let line = shades(&mut ppu);
+            let columns: Vec<_> = (0..SCREEN_WIDTH).filter(|&x| line[x] == 3).collect();
+            assert_eq!(columns, (20..=92).step_by(8).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn dmg_sprite_with_the_smaller_x_wins_then_the_earlier_in_oam() {
+        for render_mode in RENDER_MODES {
+            let mut ppu = dmg(render_mode);
+            fill_tile(&mut ppu, 1, 1, false);
+            fill_tile(&mut ppu, 2, 2, false);
+            fill_tile(&mut ppu, 3, 3, true);
+            ppu.write_register(0xFF40, 0x93);
+            sprite(&mut ppu, 0, 12, 1, 0);
+            sprite(&mut ppu, 1, 8, 2, 0);
+            sprite(&mut ppu, 2, 40, 1, 0);
+            sprite(&mut ppu, 3, 40, 2, 0);
+            // the winner's transparent pixels show the sprite behind it
+            sprite(&mut ppu, 4, 60, 3, 0);
+            sprite(&mut ppu, 5, 61, 1, 0);
+
+            let mut expected = span(0, 8, 2);
+            expected[8..12].fill(1);
+            expected[32..40].fill(1);
+            expected[52] = 3;
+            expected[53..61].fill(1);
+            assert_eq!(shades(&mut ppu), expected, "{render_mode:?}");
+        }
+    }
+
+    const BLACK: u16 = 0x0000;
+    const WHITE: u16 = 0x7FFF;
+    const RED: u16 = 0x001F;
+    const GREEN: u16 = 0x03E0;
+    const BLUE: u16 = 0x7C00;
+
+    // an RGB555 colour as it lands in the framebuffer
+    fn rgb(color: u16) -> u32 {
+        let mut palettes = [0; 64];
+        palettes[0] = color as u8;
+        palettes[1] = (color >> 8) as u8;
+        cgb_color(&palettes, 0, 0)
+    }
+
+    fn cgb(render_mode: RenderMode) -> Ppu {
+        let mut ppu = Ppu::with_model(Model::Cgb);
+        ppu.set_render_mode(render_mode);
+        ppu
+    }
+
+    // fills a palette through BCPS/BCPD (0xFF68) or OCPS/OCPD (0xFF6A)
+    fn set_palette(ppu: &mut Ppu, spec: u16, palette: u8, colors: [u16; 4]) {
+        ppu.write_register(spec, 0x80 | (palette * 8));
+        for color in colors {
+            ppu.write_register(spec + 1, color as u8);
+            ppu.write_register(spec + 1, (color >> 8) as u8);
+        }
+    }
+
+    // a CGB line of the given spans, white (the power-on BG palettes)
+    // everywhere else
+    fn colors(spans: &[(usize, usize, u16)]) -> Vec<u32> {
+        let mut line = vec![0x00FFFFFF; SCREEN_WIDTH];
+        for &(start, end, color) in spans {
+            line[start..end].fill(rgb(color));
+        }
+        line
+    }
+
+    #[test]
+    fn cgb_bg_attributes_pick_bank_flip_and_palette() {
+        for render_mode in RENDER_MODES {
+            let mut ppu = cgb(render_mode);
+            set_palette(&mut ppu, 0xFF68, 2, [BLACK, RED, GREEN, BLUE]);
+
+            // tile 1 is solid in bank 0 but only has its left column in bank 1
+            fill_tile(&mut ppu, 1, 3, false);
+            ppu.write_register(0xFF4F, 1);
+            fill_tile(&mut ppu, 1, 1, true);
+            ppu.write_register(0xFF4F, 0);
+            // tile 2 only has its bottom row, in colour 2
+            ppu.write_vram(0x802F, 0xFF);
+
+            ppu.write_vram(0x9800, 1);
+            ppu.write_vram(0x9801, 2);
+            ppu.write_register(0xFF4F, 1);
+            ppu.write_vram(0x9800, 0x08 | 0x20 | 0x02); // bank 1, xflip
+            ppu.write_vram(0x9801, 0x40 | 0x02); // yflip
+            ppu.write_vram(0x9802, 0x02);
+
+            let expected = colors(&[(0, 7, BLACK), (7, 8, RED), (8, 16, GREEN), (16, 24, BLACK)]);
+            assert_eq!(render(&mut ppu), expected, "{render_mode:?}");
+        }
+    }
+
+    // BG colour 1 with the priority attribute at 0-7, without it at 8-15
+    // and 24-31, BG colour 0 with the attribute at 16-23, and a sprite over
+    // each. The one at 24 has its own priority flag set
+    fn cgb_priority_line(render_mode: RenderMode, lcdc: u8) -> Vec<u32> {
+        let mut ppu = cgb(render_mode);
+        ppu.write_register(0xFF40, lcdc);
+        set_palette(&mut ppu, 0xFF68, 0, [WHITE, RED, RED, RED]);
+        set_palette(&mut ppu, 0xFF6A, 0, [BLACK, BLUE, BLUE, BLUE]);
+        fill_tile(&mut ppu, 1, 1, false);
+        fill_tile(&mut ppu, 2, 1, false);
+
+        for column in [0, 1, 3] {
+            ppu.write_vram(0x9800 + column, 1);
+        }
+        ppu.write_register(0xFF4F, 1);
+        ppu.write_vram(0x9800, 0x80);
+        ppu.write_vram(0x9802, 0x80);
+        ppu.write_register(0xFF4F, 0);
+
+        for (index, x) in [8, 16, 24].into_iter().enumerate() {
+            sprite(&mut ppu, index as u16, x, 2, 0);
+        }
+        sprite(&mut ppu, 3, 32, 2, 0x80);
+
+        render(&mut ppu)
+    }
+
+    #[test]
+    fn cgb_bg_priority_attribute_and_lcdc_bit_0() {
+        for render_mode in RENDER_MODES {
+            let line = cgb_priority_line(render_mode, 0x93);
+            let expected = colors(&[(0, 8, RED), (8, 24, BLUE), (24, 32, RED)]);
+            assert_eq!(line, expected, "{render_mode:?}");
+
+            // with LCDC bit 0 clear sprites go over everything
+            let line = cgb_priority_line(render_mode, 0x92);
+            let expected = colors(&[(0, 32, BLUE)]);
+            assert_eq!(line, expected, "{render_mode:?}");
+        }
+    }
+
+    #[test]
+    fn cgb_sprites_overlap_by_oam_index() {
+        for render_mode in RENDER_MODES {
+            let mut ppu = cgb(render_mode);
+            ppu.write_register(0xFF40, 0x93);
+            set_palette(&mut ppu, 0xFF6A, 0, [BLACK, BLUE, BLUE, BLUE]);
+            set_palette(&mut ppu, 0xFF6A, 1, [BLACK, RED, RED, RED]);
+            fill_tile(&mut ppu, 1, 1, false);
+            fill_tile(&mut ppu, 2, 1, true);
+
+            // the DMG would put the one further left on top
+            sprite(&mut ppu, 0, 12, 1, 0);
+            sprite(&mut ppu, 1, 8, 1, 1);
+            // a transparent pixel lets the next sprite through
+            sprite(&mut ppu, 2, 40, 2, 0);
+            sprite(&mut ppu, 3, 40, 1, 1);
+
+            let expected = colors(&[(0, 4, RED), (4, 12, BLUE), (32, 33, BLUE), (33, 40, RED)]);
+            assert_eq!(render(&mut ppu), expected, "{render_mode:?}");
+        }
+    }
+
+    #[test]
+    fn cgb_palette_specs_auto_increment() {
+        let mut ppu = Ppu::with_model(Model::Cgb);
+
+        // wraps from the last byte back to the first
+        ppu.write_register(0xFF68, 0xBE);
+        for value in [0x11, 0x22, 0x33] {
+            ppu.write_register(0xFF69, value);
+        }
+        assert_eq!(ppu.bg_palettes[0x3E..], [0x11, 0x22]);
+        assert_eq!(ppu.bg_palettes[0], 0x33);
+        assert_eq!(ppu.read_register(0xFF68), 0xC1);
+
+        // reads don't move it
+        assert_eq!(ppu.read_register(0xFF69), 0xFF);
+        assert_eq!(ppu.read_register(0xFF68), 0xC1);
+
+        // without bit 7 writes land on the same byte
+        ppu.write_register(0xFF6A, 0x05);
+        ppu.write_register(0xFF6B, 0x44);
+        ppu.write_register(0xFF6B, 0x55);
+        assert_eq!(ppu.obj_palettes[5], 0x55);
+        assert_eq!(ppu.obj_palettes[6], 0x00);
+        assert_eq!(ppu.read_register(0xFF6A), 0x45);
+        assert_eq!(ppu.read_register(0xFF6B), 0x55);
+    }
+
+    #[test]
+    fn vbk_switches_vram_banks_on_cgb_only() {
+        let mut ppu = Ppu::with_model(Model::Cgb);
+        ppu.write_register(0xFF4F, 0x01);
+        ppu.write_vram(0x8000, 0x12);
+        assert_eq!(ppu.read_register(0xFF4F), 0xFF);
+
+        // only bit 0 selects the bank
+        ppu.write_register(0xFF4F, 0xFE);
+        assert_eq!(ppu.read_register(0xFF4F), 0xFE);
+        assert_eq!(ppu.read_vram(0x8000), 0x00);
+        ppu.write_vram(0x8000, 0x34);
+
+        ppu.write_register(0xFF4F, 0x01);
+        assert_eq!(ppu.read_vram(0x8000), 0x12);
+
+        let mut ppu = Ppu::new();
+        ppu.write_register(0xFF4F, 0x01);
+        ppu.write_vram(0x8000, 0x12);
+        assert_eq!(ppu.read_register(0xFF4F), 0xFF);
+        ppu.write_register(0xFF4F, 0x00);
+        assert_eq!(ppu.read_vram(0x8000), 0x12);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::bus::{Clock, MemoryMappedDevice};
+use crate::interrupts::Interrupt;
+use crate::model::Model;
+
+// T-cycles per bit with the internal clock, 8192 Hz or the CGB's 262144 Hz
+const NORMAL_BIT_CYCLES: u16 = 512;
+const FAST_BIT_CYCLES: u16 = 16;
+
+// the link port, with nothing plugged in. Bytes sent are echoed to stdout,
+// which is how test ROMs report their results
+pub struct Serial {
+    model: Model,
+    sb: u8, // 0xFF01
+    sc: u8, // 0xFF02
+    bits_left: u8,
+    cycles: u16, // towards the next bit
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Self::with_model(Model::Dmg)
+    }
+
+    pub fn with_model(model: Model) -> Self {
+        Self {
+            model,
+            sb: 0,
+            sc: 0,
+            bits_left: 0,
+            cycles: 0,
+        }
+    }
+
+    // advances by `cycles` T-cycles of the CPU clock, returns true if a
+    // serial interrupt was requested
+    pub fn tick(&mut self, cycles: u8) -> bool {
+        // only the internal clock moves without a link partner
+        if self.bits_left == 0 || self.sc & 0x01 == 0 {
+            return false;
+        }
+
+        let bit_cycles = if self.model.is_cgb() && self.sc & 0x02 != 0 {
+            FAST_BIT_CYCLES
+        } else {
+            NORMAL_BIT_CYCLES
+        };
+
+        self.cycles += cycles as u16;
+        while self.cycles >= bit_cycles && self.bits_left > 0 {
+            self.cycles -= bit_cycles;
+
+            // nothing on the other end, so 1s get shifted in
+            self.sb = (self.sb << 1) | 1;
+            self.bits_left -= 1;
+        }
+
+        if self.bits_left == 0 {
+            self.sc &= 0x7F;
+            return true;
+        }
+
+        false
+    }
+}
+
+impl MemoryMappedDevice for Serial {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF01 => self.sb,
+            // bit 1 is the CGB's clock speed, unused on DMG
+            0xFF02 if self.model.is_cgb() => self.sc | 0x7C,
+            0xFF02 => self.sc | 0x7E,
+            _ => unreachable!("Not a serial register: 0x{:04X}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0xFF01 => self.sb = value,
+            0xFF02 => {
+                self.sc = value & 0x83;
+
+                // bit 7 set means "start transfer"
+                if value & 0x80 != 0 {
+                    self.bits_left = 8;
+                    self.cycles = 0;
+
+                    // only echo what's sent on the internal clock, an
+                    // external clock transfer is waiting for a partner
+                    if value & 0x01 != 0 {
+                        print!("{}", self.sb as char);
+                    }
+                }
+            }
+            _ => unreachable!("Not a serial register: 0x{:04X}", addr),
+        }
+    }
+
+    fn tick(&mut self, clock: &Clock) -> u8 {
+        if Serial::tick(self, clock.cycles) {
+            Interrupt::Serial.mask()
+        } else {
+            0
+        }
+    }
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,222 @@
+use crate::bus::{Clock, MemoryMappedDevice};
+use crate::interrupts::Interrupt;
+
+// what TIMA is doing after it overflowed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum TimaState {
+    Counting,
+    // TIMA reads 0x00 for one M-cycle before TMA is loaded,
+    // a write to TIMA in this window cancels the reload
+    Overflowed(u8),
+    // the M-cycle TMA is copied in, TIMA writes are ignored and
+    // TMA writes go straight through to TIMA
+    Reloading(u8),
+}
+
+pub struct Timer {
+    // internal 16-bit counter, DIV is its upper byte
+    div: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    state: TimaState,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self {
+            div: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            state: TimaState::Counting,
+        }
+    }
+
+    // advances by `cycles` T-cycles, returns true if a timer interrupt was requested
+    pub fn tick(&mut self, cycles: u8) -> bool {
+        let mut interrupt = false;
+
+        for _ in 0..cycles {
+            self.state = match self.state {
+                TimaState::Overflowed(1) => {
+                    self.tima = self.tma;
+                    interrupt = true;
+                    TimaState::Reloading(4)
+                }
+                TimaState::Overflowed(n) => TimaState::Overflowed(n - 1),
+                TimaState::Reloading(1) => TimaState::Counting,
+                TimaState::Reloading(n) => TimaState::Reloading(n - 1),
+                TimaState::Counting => TimaState::Counting,
+            };
+
+            let old = self.signal();
+            self.div = self.div.wrapping_add(1);
+            self.detect_falling_edge(old);
+        }
+
+        interrupt
+    }
+
+    // the selected DIV bit ANDed with the enable bit. TIMA counts on the
+    // falling edge of this, which is why DIV/TAC writes can bump it
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0b00 => 9, // 4096 Hz
+            0b01 => 3, // 262144 Hz
+            0b10 => 5, // 65536 Hz
+            _ => 7,    // 16384 Hz
+        };
+
+        self.tac & 0x04 != 0 && self.div & (1 << bit) != 0
+    }
+
+    fn detect_falling_edge(&mut self, old: bool) {
+        if old && !self.signal() {
+            self.increment_tima();
+        }
+    }
+
+    fn increment_tima(&mut self) {
+        let (result, overflow) = self.tima.overflowing_add(1);
+        self.tima = result;
+
+        if overflow {
+            self.state = TimaState::Overflowed(4);
+        }
+    }
+
+    // the full internal counter, DIV is the upper byte
+    pub fn counter(&self) -> u16 {
+        self.div
+    }
+}
+
+impl MemoryMappedDevice for Timer {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF04 => (self.div >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            0xFF07 => self.tac | 0xF8, // upper bits unused
+            _ => unreachable!("Not a timer register: 0x{:04X}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            // any write resets the whole internal counter
+            0xFF04 => {
+                let old = self.signal();
+                self.div = 0;
+                self.detect_falling_edge(old);
+            }
+
+            0xFF05 => match self.state {
+                TimaState::Overflowed(_) => {
+                    self.tima = value;
+                    self.state = TimaState::Counting;
+                }
+                TimaState::Reloading(_) => {}
+                TimaState::Counting => self.tima = value,
+            },
+
+            0xFF06 => {
+                self.tma = value;
+                if let TimaState::Reloading(_) = self.state {
+                    self.tima = value;
+                }
+            }
+
+            0xFF07 => {
+                let old = self.signal();
+                self.tac = value & 0x07;
+                self.detect_falling_edge(old);
+            }
+
+            _ => unreachable!("Not a timer register: 0x{:04X}", addr),
+        }
+    }
+
+    fn tick(&mut self, clock: &Clock) -> u8 {
+        if !clock.div_held && Timer::tick(self, clock.cycles) {
+            Interrupt::Timer.mask()
+        } else {
+            0
+        }
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // TIMA at 0xFF and TAC at 262144 Hz, overflowing after exactly 16 cycles
+    fn overflowed_timer() -> Timer {
+        let mut timer = Timer::new();
+        timer.write(0xFF06, 0x23);
+        timer.write(0xFF07, 0x05);
+        timer.write(0xFF05, 0xFF);
+
+        assert!(!timer.tick(16));
+        timer
+    }
+
+    #[test]
+    fn tima_reads_0_for_an_m_cycle_before_the_reload() {
+        let mut timer = overflowed_timer();
+        assert_eq!(timer.read(0xFF05), 0x00);
+
+        assert!(!timer.tick(3));
+        assert_eq!(timer.read(0xFF05), 0x00);
+
+        assert!(timer.tick(1));
+        assert_eq!(timer.read(0xFF05), 0x23);
+    }
+
+    #[test]
+    fn writing_tima_during_the_delay_cancels_the_reload() {
+        let mut timer = overflowed_timer();
+        timer.write(0xFF05, 0x50);
+
+        assert!(!timer.tick(4));
+        assert_eq!(timer.read(0xFF05), 0x50);
+    }
+
+    #[test]
+    fn tima_writes_are_ignored_while_reloading() {
+        let mut timer = overflowed_timer();
+        assert!(timer.tick(4));
+
+        timer.write(0xFF05, 0x50);
+        assert_eq!(timer.read(0xFF05), 0x23);
+
+        // but TMA goes straight through
+        timer.write(0xFF06, 0x77);
+        assert_eq!(timer.read(0xFF05), 0x77);
+
+        // after the reload M-cycle TIMA is writable again
+        timer.tick(4);
+        timer.write(0xFF05, 0x50);
+        assert_eq!(timer.read(0xFF05), 0x50);
+    }
+
+    #[test]
+    fn resetting_div_can_bump_tima() {
+        let mut timer = Timer::new();
+        timer.write(0xFF07, 0x05);
+        timer.tick(8);
+        assert_eq!(timer.read(0xFF05), 0x00);
+
+        // bit 3 of the counter is set, clearing it is a falling edge
+        timer.write(0xFF04, 0x00);
+        assert_eq!(timer.read(0xFF05), 0x01);
+        assert_eq!(timer.counter(), 0);
+    }
+}